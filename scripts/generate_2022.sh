@@ -1,26 +1,7 @@
 #!/bin/sh
 
-# Script that sends requests to the server to generate PDFs
-# Server = http://localhost:5000
-# Example: http://localhost:5000/?day=10&month=10&year=2022
-# This generates a PDF for the 10th of October 2022
+# Downloads the substitutions for every school day of 2022 into the cache.
+# Weekends and the winter break are skipped, the server doesn't have to be running.
+# Run it from the folder with zastepstwa.toml (or set ZASTEPSTWA_CONFIG).
 
-# GENERATE PDFS FOR 2022
-
-# Loop through the days
-for a in $(seq 1 31); do
-  # Loop through the months
-  for b in $(seq 1 12); do
-    # Loop through the years
-    for c in 2022; do
-      # Generate the PDF for the date
-      #
-      # Note: You may need to escape the & character with a \
-      # This is because the & character is a special character in the shell
-      #   
-      # Example: http://localhost:5000/?day=10\&month=10\&year=2022
-      #
-      curl http://localhost:5000/?day=$a\&month=$b\&year=$c
-    done
-  done
-done
\ No newline at end of file
+cargo run --release -- backfill --from 01.01.2022 --to 31.12.2022
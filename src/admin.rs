@@ -0,0 +1,114 @@
+use actix_web::{delete, post, web, HttpRequest, HttpResponse};
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+
+use crate::config::Config;
+use crate::error::ZastepstwaError;
+use crate::maintenance::{Maintenance, MaintenanceState};
+use crate::state::AppState;
+
+// Body of POST /admin/maintenance, both fields are optional
+#[derive(Deserialize)]
+struct EnableMaintenance {
+    message: Option<String>,
+    until: Option<DateTime<Local>>,
+}
+
+// Check the "Authorization: Bearer <token>" header against the configured token
+fn authorize(config: &Config, req: &HttpRequest) -> Result<(), ZastepstwaError> {
+    // No token configured, the admin API is disabled
+    let token = config
+        .admin
+        .token
+        .as_ref()
+        .ok_or(ZastepstwaError::AdminDisabled)?;
+    let given = req
+        .headers()
+        .get("Authorization")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    match given {
+        Some(given) if constant_time_eq(given.as_bytes(), token.as_bytes()) => Ok(()),
+        _ => Err(ZastepstwaError::Unauthorized),
+    }
+}
+
+// Compare without returning early, so the token can't be guessed from response times
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+// Turn maintenance on, optionally with a new message and a time when it should end
+// curl -X POST -H "Authorization: Bearer <token>" -H "Content-Type: application/json" \
+//      -d '{"message": "Przerwa techniczna", "until": "2023-01-30T07:00:00+01:00"}' /admin/maintenance
+#[post("/admin/maintenance")]
+async fn enable_maintenance(
+    req: HttpRequest,
+    state: web::Data<AppState>,
+    body: web::Bytes,
+) -> Result<HttpResponse, ZastepstwaError> {
+    authorize(&state.config, &req)?;
+    // An empty body just turns maintenance on with the previous message
+    let EnableMaintenance { message, until } = if body.is_empty() {
+        EnableMaintenance {
+            message: None,
+            until: None,
+        }
+    } else {
+        serde_json::from_slice(&body)
+            .map_err(|e| ZastepstwaError::InvalidBody(format!("Nieprawidłowe dane: {}", e)))?
+    };
+    if until.is_some_and(|until| until <= Local::now()) {
+        return Err(ZastepstwaError::InvalidParameter(
+            "Czas zakończenia musi być w przyszłości".to_string(),
+        ));
+    }
+
+    let new = Maintenance {
+        enabled: true,
+        // Keep the previous message if a new one wasn't given
+        message: message.unwrap_or_else(|| state.maintenance.get().message),
+        until,
+    };
+    update(&state.maintenance, new).await
+}
+
+// Turn maintenance off
+#[delete("/admin/maintenance")]
+async fn disable_maintenance(
+    req: HttpRequest,
+    state: web::Data<AppState>,
+) -> Result<HttpResponse, ZastepstwaError> {
+    authorize(&state.config, &req)?;
+    let new = Maintenance {
+        enabled: false,
+        until: None,
+        ..state.maintenance.get()
+    };
+    update(&state.maintenance, new).await
+}
+
+#[derive(Serialize)]
+struct MaintenanceResponse {
+    code: u16,
+    maintenance: Maintenance,
+}
+
+async fn update(
+    maintenance: &MaintenanceState,
+    new: Maintenance,
+) -> Result<HttpResponse, ZastepstwaError> {
+    maintenance.set(new.clone()).await.map_err(|e| {
+        log::error!("Error while saving maintenance state: {}", e);
+        ZastepstwaError::Internal("Nie udało się zapisać stanu przerwy technicznej".to_string())
+    })?;
+    log::info!(
+        "Maintenance {} (until: {:?})",
+        if new.enabled { "enabled" } else { "disabled" },
+        new.until
+    );
+    Ok(HttpResponse::Ok().json(MaintenanceResponse {
+        code: 200,
+        maintenance: new,
+    }))
+}
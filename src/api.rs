@@ -0,0 +1,243 @@
+use actix_web::error::InternalError;
+use actix_web::{get, web, HttpResponse, ResponseError, Scope};
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+
+use crate::error::ZastepstwaError;
+use crate::index::{Entry, UpstreamStatus};
+use crate::state::AppState;
+use crate::{auto_link, requested_date, Date, Response, When};
+
+// /api/v1 is the old API under a fixed name. The iOS Shortcut reads exactly {code, link} or {code, error}
+// and always gets a 200, so nothing here may change, new fields go to /api/v2.
+// /api/v1/?day=10&month=10&year=2022
+// /api/v1/auto/?when=today
+pub fn v1() -> Scope {
+    web::scope("/api/v1")
+        // Bad query parameters get the old shape too
+        .app_data(web::QueryConfig::default().error_handler(|err, _| {
+            let response = legacy(Err(ZastepstwaError::InvalidParameter(err.to_string())));
+            InternalError::from_response(err, response).into()
+        }))
+        .service(legacy_get_data)
+        .service(legacy_auto_get_data)
+}
+
+// The old error body, without "kind"
+#[derive(Debug, Serialize)]
+struct LegacyError {
+    code: u16,
+    error: String,
+}
+
+// The status only goes into "code", the HTTP status is always 200
+fn legacy(result: Result<String, ZastepstwaError>) -> HttpResponse {
+    match result {
+        Ok(link) => HttpResponse::Ok().json(Response { code: 200, link }),
+        Err(e) => HttpResponse::Ok().json(LegacyError {
+            code: e.status_code().as_u16(),
+            error: e.to_string(),
+        }),
+    }
+}
+
+#[get("/")]
+async fn legacy_get_data(state: web::Data<AppState>, date: web::Query<Date>) -> HttpResponse {
+    legacy(state.ready_file(&date).await.map(|ready| ready.link))
+}
+
+#[get("/auto/")]
+async fn legacy_auto_get_data(state: web::Data<AppState>, when: web::Query<When>) -> HttpResponse {
+    legacy(auto_link(&state, &when.when).await)
+}
+
+pub fn v2() -> Scope {
+    web::scope("/api/v2").service(file)
+}
+
+// ?date=10.10.2022 or ?when=today|tomorrow
+#[derive(Debug, Deserialize)]
+pub struct FileRequest {
+    pub date: Option<String>,
+    pub when: Option<String>,
+}
+
+// The link to the PDF with everything we know about the copy behind it
+#[derive(Debug, Serialize)]
+pub struct FileResponse {
+    pub code: u16,
+    pub link: String,
+    // dd.mm.yyyy
+    pub date: String,
+    pub file: FileMeta,
+}
+
+#[derive(Debug, PartialEq, Eq, Serialize)]
+pub struct FileMeta {
+    // When the served version was downloaded
+    pub fetched_at: DateTime<Local>,
+    // When we last asked the school website about this date
+    pub checked_at: DateTime<Local>,
+    // Seconds since the served version was downloaded
+    pub age: i64,
+    // The copy couldn't be refreshed (the website is offline or the last check is too old)
+    pub stale: bool,
+    pub revision: u32,
+    pub sha256: String,
+    pub size: u64,
+    // What the school website said the last time we asked
+    pub source_status: UpstreamStatus,
+}
+
+impl FileMeta {
+    pub fn new(entry: &Entry, time_min: i64, now: DateTime<Local>) -> FileMeta {
+        // Dates cached before revisions were kept have no history, the last change is the download
+        let fetched_at = entry
+            .history
+            .last()
+            .map_or(entry.last_changed, |revision| revision.fetched_at);
+        FileMeta {
+            fetched_at,
+            checked_at: entry.last_checked,
+            age: (now - fetched_at).num_seconds().max(0),
+            stale: !entry.is_fresh(time_min),
+            revision: entry.revisions,
+            sha256: entry.sha256.clone(),
+            size: entry.size,
+            source_status: entry.upstream_status,
+        }
+    }
+}
+
+// /api/v2/file?date=10.10.2022
+// /api/v2/file?when=today
+#[get("/file")]
+async fn file(
+    state: web::Data<AppState>,
+    query: web::Query<FileRequest>,
+) -> Result<HttpResponse, ZastepstwaError> {
+    let date = requested_date(query.date.as_deref(), query.when.as_deref())?;
+    let ready = state.ready_file(&date).await?;
+    // ready_file only succeeds with a cached copy, and every cached copy is in the index
+    let entry = state.index.get(ready.date).ok_or_else(|| {
+        log::error!("{} is cached, but missing from the cache index", ready.name);
+        ZastepstwaError::Storage
+    })?;
+
+    Ok(HttpResponse::Ok().json(FileResponse {
+        code: 200,
+        link: ready.link,
+        date: ready.name,
+        file: FileMeta::new(&entry, state.config.cache.time_min, Local::now()),
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::index::Revision;
+    use crate::source::Validators;
+    use chrono::Duration;
+
+    #[test]
+    fn file_metadata() {
+        let now = Local::now();
+        let mut entry = Entry {
+            first_seen: now - Duration::hours(3),
+            last_checked: now - Duration::minutes(5),
+            last_changed: now - Duration::hours(1),
+            upstream_status: UpstreamStatus::NotModified,
+            sha256: "abc".to_string(),
+            size: 1234,
+            revisions: 2,
+            history: vec![
+                Revision {
+                    number: 1,
+                    sha256: "def".to_string(),
+                    size: 1000,
+                    fetched_at: now - Duration::hours(3),
+                },
+                Revision {
+                    number: 2,
+                    sha256: "abc".to_string(),
+                    size: 1234,
+                    fetched_at: now - Duration::hours(1),
+                },
+            ],
+            hits: 0,
+            validators: Validators::default(),
+        };
+
+        let meta = FileMeta::new(&entry, 30, now);
+        assert_eq!(meta.age, 3600);
+        assert_eq!(meta.revision, 2);
+        assert!(!meta.stale);
+
+        // The website went down, the old copy is still served
+        entry.upstream_status = UpstreamStatus::Offline;
+        assert!(FileMeta::new(&entry, 30, now).stale);
+        entry.upstream_status = UpstreamStatus::Ok;
+        assert!(FileMeta::new(&entry, 1, now).stale);
+    }
+
+    // The iOS Shortcut reads these exact keys and nothing else, a new field would already be a breaking change for v1
+    #[actix_web::test]
+    async fn legacy_shapes() {
+        use actix_web::test;
+
+        let dir = std::env::temp_dir().join(format!("zastepstwa-api-{}", std::process::id()));
+        let _ = tokio::fs::remove_dir_all(&dir).await;
+        tokio::fs::create_dir_all(&dir).await.unwrap();
+        let mut config = crate::config::Config::default();
+        config.cache.dir = dir.clone();
+        config.server.domain = "https://example.com".to_string();
+        // Nothing here may reach the school website
+        config.upstream.url = "http://127.0.0.1:9/".to_string();
+        let maintenance = crate::maintenance::MaintenanceState::inactive(&config);
+        let state = AppState::open(config, maintenance).await.unwrap();
+        // A fresh copy of 10.10.2022 is served without a download
+        let date = chrono::NaiveDate::from_ymd_opt(2022, 10, 10).unwrap();
+        tokio::fs::write(dir.join("10.10.2022.pdf"), b"%PDF")
+            .await
+            .unwrap();
+        state
+            .index
+            .record_download(date, b"%PDF", crate::source::Validators::default())
+            .await
+            .unwrap();
+        let app = test::init_service(
+            actix_web::App::new()
+                .app_data(web::Data::new(state))
+                .service(v1()),
+        )
+        .await;
+
+        let body = |uri: &'static str| {
+            let app = &app;
+            async move {
+                let request = test::TestRequest::get().uri(uri).to_request();
+                let response = test::call_service(app, request).await;
+                assert_eq!(response.status(), 200, "{}", uri);
+                test::read_body_json::<serde_json::Value, _>(response).await
+            }
+        };
+        assert_eq!(
+            body("/api/v1/?day=10&month=10&year=2022").await,
+            serde_json::json!({"code": 200, "link": "https://example.com/files/10.10.2022.pdf"})
+        );
+        assert_eq!(
+            body("/api/v1/?day=15&month=10&year=2022").await,
+            serde_json::json!({"code": 422, "error": "Wybrana data to weekend!"})
+        );
+        let invalid = body("/api/v1/auto/?when=yesterday").await;
+        assert_eq!(
+            invalid,
+            serde_json::json!({"code": 422, "error": "Nieprawidłowa wartość parametru 'when'"})
+        );
+        let missing = body("/api/v1/?day=10").await;
+        let keys: Vec<&String> = missing.as_object().unwrap().keys().collect();
+        assert_eq!(keys, ["code", "error"]);
+
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+    }
+}
@@ -0,0 +1,175 @@
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use tokio::io::AsyncWriteExt;
+
+// Makes temp file names unique when several downloads run at the same time
+static TEMP_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+// Cached PDF for a date in the dd.mm.yyyy format
+pub fn pdf_path(dir: &Path, date: &str) -> PathBuf {
+    dir.join(format!("{}.pdf", date))
+}
+
+// Older versions are kept in ./cached/revisions/{dd.mm.yyyy}/{number}.pdf
+pub fn revision_path(dir: &Path, date: &str, number: u32) -> PathBuf {
+    revisions_dir(dir, date).join(format!("{}.pdf", number))
+}
+
+// Folder with every version of a date
+pub fn revisions_dir(dir: &Path, date: &str) -> PathBuf {
+    dir.join("revisions").join(date)
+}
+
+// Save a downloaded file so readers never see a missing or half-written file.
+// The bytes go to a temp file in the same folder first (so the rename can't cross filesystems),
+// get checked and synced to disk, and only then replace the old copy in one atomic rename.
+pub async fn store(path: &Path, bytes: &[u8]) -> std::io::Result<()> {
+    let temp = temp_path(path);
+    let result = write_and_rename(&temp, path, bytes).await;
+    if result.is_err() {
+        // Don't leave the temp file behind, the old copy is still untouched
+        let _ = tokio::fs::remove_file(&temp).await;
+    }
+    result
+}
+
+async fn write_and_rename(temp: &Path, path: &Path, bytes: &[u8]) -> std::io::Result<()> {
+    if let Some(parent) = path.parent() {
+        tokio::fs::create_dir_all(parent).await?;
+    }
+    // An empty download would replace a good copy with nothing
+    if bytes.is_empty() {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            "downloaded file is empty",
+        ));
+    }
+
+    let mut file = tokio::fs::File::create(temp).await?;
+    // Write the PDF to the temp file
+    file.write_all(bytes).await?;
+    file.flush().await?;
+    // Make sure the data is on disk before it replaces the old copy
+    file.sync_all().await?;
+    drop(file);
+
+    // Check that everything got written before swapping the files
+    let written = tokio::fs::metadata(temp).await?.len();
+    if written != bytes.len() as u64 {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::UnexpectedEof,
+            format!("wrote {} of {} bytes", written, bytes.len()),
+        ));
+    }
+
+    tokio::fs::rename(temp, path).await
+}
+
+// For ./cached/10.10.2022.pdf this is ./cached/.10.10.2022.pdf.<pid>.<n>.tmp
+fn temp_path(path: &Path) -> PathBuf {
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let n = TEMP_COUNTER.fetch_add(1, Ordering::Relaxed);
+    path.with_file_name(format!(".{}.{}.{}.tmp", name, std::process::id(), n))
+}
+
+// Temp files are hidden, so anything listing the cache can skip them
+pub fn is_temp_file(name: &str) -> bool {
+    name.starts_with('.') && name.ends_with(".tmp")
+}
+
+// Remove temp files left over after a crash in the middle of a download (including the revisions folders)
+pub async fn remove_temp_files(dir: &Path) -> std::io::Result<()> {
+    let mut dirs = vec![dir.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        let mut entries = tokio::fs::read_dir(&dir).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            if entry.file_type().await?.is_dir() {
+                dirs.push(entry.path());
+            } else if is_temp_file(&entry.file_name().to_string_lossy()) {
+                log::info!("Removing leftover temp file {}", entry.path().display());
+                tokio::fs::remove_file(entry.path()).await?;
+            }
+        }
+    }
+    Ok(())
+}
+
+// Held by the server for as long as it runs and by the commands that change the cache. Each of them
+// keeps its own copy of the index in memory and saves it whole, so two of them at once would
+// overwrite each other's changes. The lock goes away with the process, even after a crash.
+pub struct CacheLock {
+    _file: std::fs::File,
+}
+
+// None if someone else already holds the lock
+pub fn lock(dir: &Path) -> std::io::Result<Option<CacheLock>> {
+    let file = std::fs::OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(dir.join(".lock"))?;
+    match file.try_lock() {
+        Ok(()) => Ok(Some(CacheLock { _file: file })),
+        Err(std::fs::TryLockError::WouldBlock) => Ok(None),
+        Err(std::fs::TryLockError::Error(e)) => Err(e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn store_and_cleanup() {
+        let dir = std::env::temp_dir().join(format!("zastepstwa-cache-{}", std::process::id()));
+        let _ = tokio::fs::remove_dir_all(&dir).await;
+        let pdf = pdf_path(&dir, "10.10.2022");
+        // Everything in the folder that isn't a finished file
+        let temp_files = || {
+            std::fs::read_dir(&dir)
+                .unwrap()
+                .filter(|entry| {
+                    is_temp_file(&entry.as_ref().unwrap().file_name().to_string_lossy())
+                })
+                .count()
+        };
+
+        store(&pdf, b"first").await.unwrap();
+        store(&pdf, b"second").await.unwrap();
+        assert_eq!(tokio::fs::read(&pdf).await.unwrap(), b"second");
+
+        // A failed write keeps the old copy and cleans up after itself
+        assert!(store(&pdf, b"").await.is_err());
+        assert_eq!(tokio::fs::read(&pdf).await.unwrap(), b"second");
+        // The rename fails when the target is a folder
+        let blocked = pdf_path(&dir, "11.10.2022");
+        tokio::fs::create_dir_all(&blocked).await.unwrap();
+        assert!(store(&blocked, b"third").await.is_err());
+        assert!(blocked.is_dir());
+        assert_eq!(temp_files(), 0);
+
+        // What a crash leaves behind, in the cache and in the revisions folders
+        let revisions = revisions_dir(&dir, "10.10.2022");
+        tokio::fs::create_dir_all(&revisions).await.unwrap();
+        tokio::fs::write(temp_path(&pdf), b"half").await.unwrap();
+        tokio::fs::write(temp_path(&revisions.join("1.pdf")), b"half")
+            .await
+            .unwrap();
+        assert_eq!(temp_files(), 1);
+        remove_temp_files(&dir).await.unwrap();
+        assert_eq!(temp_files(), 0);
+        assert_eq!(std::fs::read_dir(&revisions).unwrap().count(), 0);
+        assert!(pdf.exists());
+
+        // Only one process at a time may change the cache
+        let held = lock(&dir).unwrap().unwrap();
+        assert!(lock(&dir).unwrap().is_none());
+        drop(held);
+        assert!(lock(&dir).unwrap().is_some());
+
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+    }
+}
@@ -0,0 +1,78 @@
+use chrono::{Datelike, Duration, NaiveDate, Weekday};
+
+use crate::config::Holiday;
+
+// The school calendar: which days have lessons, and so can have substitutions
+
+pub fn is_weekend(date: NaiveDate) -> bool {
+    matches!(date.weekday(), Weekday::Sat | Weekday::Sun)
+}
+
+// The winter break is always 16.01 - 29.01
+pub fn is_winter_break(date: NaiveDate) -> bool {
+    date.month() == 1 && (16..=29).contains(&date.day())
+}
+
+// The breaks and free days from school.holidays
+pub fn is_holiday(holidays: &[Holiday], date: NaiveDate) -> bool {
+    holidays.iter().any(|holiday| holiday.contains(date))
+}
+
+pub fn is_school_day(holidays: &[Holiday], date: NaiveDate) -> bool {
+    !is_weekend(date) && !is_winter_break(date) && !is_holiday(holidays, date)
+}
+
+// The first school day after a date
+pub fn next_school_day(holidays: &[Holiday], date: NaiveDate) -> NaiveDate {
+    let mut date = date + Duration::days(1);
+    while !is_school_day(holidays, date) {
+        date += Duration::days(1);
+    }
+    date
+}
+
+// The last school day before a date
+pub fn previous_school_day(holidays: &[Holiday], date: NaiveDate) -> NaiveDate {
+    let mut date = date - Duration::days(1);
+    while !is_school_day(holidays, date) {
+        date -= Duration::days(1);
+    }
+    date
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn school_days() {
+        let date = |day, month| NaiveDate::from_ymd_opt(2023, month, day).unwrap();
+        // Friday -> Monday
+        assert_eq!(next_school_day(&[], date(6, 10)), date(9, 10));
+        // The winter break is skipped as a whole
+        assert!(!is_school_day(&[], date(16, 1)));
+        assert_eq!(next_school_day(&[], date(13, 1)), date(30, 1));
+        assert_eq!(previous_school_day(&[], date(30, 1)), date(13, 1));
+        assert_eq!(previous_school_day(&[], date(9, 10)), date(6, 10));
+        assert!(is_school_day(&[], date(10, 10)));
+
+        // Configured breaks are skipped the same way, both ends included
+        let holidays = [
+            Holiday {
+                from: date(10, 10),
+                to: date(10, 10),
+            },
+            Holiday {
+                from: date(12, 10),
+                to: date(16, 10),
+            },
+        ];
+        assert!(!is_school_day(&holidays, date(10, 10)));
+        assert!(is_school_day(&holidays, date(11, 10)));
+        assert_eq!(next_school_day(&holidays, date(9, 10)), date(11, 10));
+        assert_eq!(next_school_day(&holidays, date(11, 10)), date(17, 10));
+        assert_eq!(previous_school_day(&holidays, date(17, 10)), date(11, 10));
+        assert_eq!("2023-10-12..2023-10-16".parse(), Ok(holidays[1]));
+        assert_eq!("2023-10-10".parse(), Ok(holidays[0]));
+    }
+}
@@ -0,0 +1,262 @@
+use actix_web::{get, web, HttpResponse};
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+
+use crate::error::ZastepstwaError;
+use crate::index::Entry;
+use crate::parser::Substitution;
+use crate::requested_date;
+use crate::state::AppState;
+
+// A record that is in both versions, but with different details
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Modified {
+    pub before: Substitution,
+    pub after: Substitution,
+    // Names of the fields that changed
+    pub changed: Vec<&'static str>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct Changes {
+    pub added: Vec<Substitution>,
+    pub removed: Vec<Substitution>,
+    pub modified: Vec<Modified>,
+}
+
+impl Changes {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.modified.is_empty()
+    }
+
+    // Only the changes of one class (and optionally one group)
+    pub fn for_class(self, class: &str, group: Option<&str>) -> Changes {
+        Changes {
+            added: self
+                .added
+                .into_iter()
+                .filter(|record| record.is_for(class, group))
+                .collect(),
+            removed: self
+                .removed
+                .into_iter()
+                .filter(|record| record.is_for(class, group))
+                .collect(),
+            modified: self
+                .modified
+                .into_iter()
+                .filter(|change| {
+                    change.before.is_for(class, group) || change.after.is_for(class, group)
+                })
+                .collect(),
+        }
+    }
+}
+
+// The same lesson of the same class, taken from the same teacher
+fn same_lesson(a: &Substitution, b: &Substitution) -> bool {
+    a.lesson == b.lesson
+        && a.class == b.class
+        && a.group == b.group
+        && a.absent_teacher == b.absent_teacher
+}
+
+// Compare two versions of the substitutions for a date. Records that didn't change are skipped,
+// records for the same lesson with different details are "modified" and the rest were added or removed.
+pub fn diff(old: &[Substitution], new: &[Substitution]) -> Changes {
+    let mut old: Vec<&Substitution> = old.iter().collect();
+    let mut added: Vec<&Substitution> = Vec::new();
+    for record in new {
+        match old.iter().position(|previous| *previous == record) {
+            Some(position) => {
+                old.remove(position);
+            }
+            None => added.push(record),
+        }
+    }
+
+    let mut changes = Changes::default();
+    for record in added {
+        match old
+            .iter()
+            .position(|previous| same_lesson(previous, record))
+        {
+            Some(position) => {
+                let before = old.remove(position);
+                changes.modified.push(Modified {
+                    before: before.clone(),
+                    after: record.clone(),
+                    changed: changed_fields(before, record),
+                });
+            }
+            None => changes.added.push(record.clone()),
+        }
+    }
+    changes.removed = old.into_iter().cloned().collect();
+    changes
+}
+
+fn changed_fields(before: &Substitution, after: &Substitution) -> Vec<&'static str> {
+    let mut changed = Vec::new();
+    if before.substitute_teacher != after.substitute_teacher {
+        changed.push("substitute_teacher");
+    }
+    if before.subject != after.subject {
+        changed.push("subject");
+    }
+    if before.room != after.room {
+        changed.push("room");
+    }
+    if before.note != after.note {
+        changed.push("note");
+    }
+    if before.kind != after.kind {
+        changed.push("kind");
+    }
+    changed
+}
+
+// ?since=2 is a revision number, ?since=2022-10-10T07:30:00+02:00 means "what I saw at that time"
+fn base_revision(entry: &Entry, since: &str) -> Result<Option<u32>, ZastepstwaError> {
+    if let Ok(number) = since.parse::<u32>() {
+        // Older versions may have been removed by the retention rules
+        if !entry
+            .history
+            .iter()
+            .any(|revision| revision.number == number)
+        {
+            return Err(ZastepstwaError::NotFound(format!(
+                "Nie ma wersji {} zastępstw",
+                number
+            )));
+        }
+        return Ok(Some(number));
+    }
+    let since: DateTime<Local> = DateTime::parse_from_rfc3339(since)
+        .map_err(|_| {
+            ZastepstwaError::InvalidParameter(
+                "Parametr 'since' musi być numerem wersji albo datą w formacie RFC 3339"
+                    .to_string(),
+            )
+        })?
+        .into();
+    // Before the first download there was nothing, so everything is new
+    Ok(entry
+        .history
+        .iter()
+        .filter(|revision| revision.fetched_at <= since)
+        .map(|revision| revision.number)
+        .max())
+}
+
+#[derive(Deserialize)]
+struct ChangesQuery {
+    date: Option<String>,
+    when: Option<String>,
+    since: String,
+    class: Option<String>,
+    group: Option<String>,
+}
+
+#[derive(Serialize)]
+struct ChangesResponse<'a> {
+    code: u16,
+    link: String,
+    date: String,
+    // The version compared with, none means everything is new
+    from: Option<u32>,
+    to: u32,
+    class: Option<&'a str>,
+    group: Option<&'a str>,
+    changed: bool,
+    // added, removed and modified
+    #[serde(flatten)]
+    changes: Changes,
+}
+
+// What changed in the substitutions since an earlier version
+// /api/substitutions/changes?date=10.10.2022&since=1
+// /api/substitutions/changes?when=today&since=2022-10-10T07:30:00%2B02:00&class=3TI
+#[get("/api/substitutions/changes")]
+async fn substitution_changes(
+    state: web::Data<AppState>,
+    query: web::Query<ChangesQuery>,
+) -> Result<HttpResponse, ZastepstwaError> {
+    let date = requested_date(query.date.as_deref(), query.when.as_deref())?;
+    // Refresh first, so the comparison is with the newest version
+    let ready = state.ready_file(&date).await?;
+    let (link, date) = (ready.link, ready.name);
+    let entry = state
+        .index
+        .get(ready.date)
+        .ok_or_else(|| ZastepstwaError::NotPublished(date.clone()))?;
+
+    let from = base_revision(&entry, &query.since)?;
+    let to = entry.revisions;
+    let old = match from {
+        Some(number) => {
+            state
+                .parsed
+                .read(&state.config.cached_revision(&date, number))
+                .await?
+        }
+        None => Default::default(),
+    };
+    let new = state
+        .parsed
+        .read(&state.config.cached_revision(&date, to))
+        .await?;
+    let mut changes = diff(&old, &new);
+    if let Some(class) = &query.class {
+        changes = changes.for_class(class, query.group.as_deref());
+    }
+
+    Ok(HttpResponse::Ok().json(ChangesResponse {
+        code: 200,
+        link,
+        date,
+        from,
+        to,
+        class: query.class.as_deref(),
+        group: query.group.as_deref(),
+        changed: !changes.is_empty(),
+        changes,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Kind;
+
+    #[test]
+    fn added_removed_and_modified() {
+        let old = crate::parser::parse(include_bytes!("../tests/fixtures/basic.pdf")).unwrap();
+        let mut new = old.clone();
+        // 2A's physics isn't cancelled after all
+        new[2].kind = Kind::Substituted;
+        new[2].substitute_teacher = Some("Anna Nowak".to_string());
+        new[2].note = None;
+        // 4TE's history is gone, 1A got a new substitution
+        let removed = new.remove(6);
+        let mut added = new[0].clone();
+        added.class = "1A".to_string();
+        new.push(added.clone());
+
+        let changes = diff(&old, &new);
+        assert_eq!(changes.added, [added]);
+        assert_eq!(changes.removed, [removed]);
+        assert_eq!(changes.modified.len(), 1);
+        assert_eq!(changes.modified[0].after.class, "2A");
+        assert_eq!(
+            changes.modified[0].changed,
+            ["substitute_teacher", "note", "kind"]
+        );
+
+        assert!(diff(&old, &old).is_empty());
+        assert_eq!(diff(&[], &old).added.len(), old.len());
+        let only_2a = changes.for_class("2a", None);
+        assert!(only_2a.added.is_empty() && only_2a.removed.is_empty());
+        assert_eq!(only_2a.modified.len(), 1);
+    }
+}
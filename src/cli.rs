@@ -0,0 +1,323 @@
+use chrono::{Duration, Local, NaiveDate};
+use clap::{Parser, Subcommand};
+
+use crate::cache::{self, CacheLock};
+use crate::calendar;
+use crate::config::Config;
+use crate::error::ZastepstwaError;
+use crate::index::{sha256_hex, Index};
+use crate::maintenance::MaintenanceState;
+use crate::retention;
+use crate::state::{self, AppState};
+use crate::{resolve_when, Date};
+
+#[derive(Parser)]
+#[command(version, about = "Substitutions server and cache tools")]
+pub struct Cli {
+    // Without a subcommand the server starts, like it always did
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Start the HTTP server (the default)
+    Serve,
+    /// Download the substitutions for one date (dd.mm.yyyy, yyyy-mm-dd, today or tomorrow)
+    Fetch { date: String },
+    /// Download every school day between two dates
+    Backfill {
+        #[arg(long, value_parser = parse_date)]
+        from: NaiveDate,
+        #[arg(long, value_parser = parse_date)]
+        to: NaiveDate,
+        /// Pause between downloads, so the school website isn't flooded
+        #[arg(long, default_value_t = 500)]
+        delay_ms: u64,
+    },
+    /// Look after the cache folder
+    Cache {
+        #[command(subcommand)]
+        command: CacheCommand,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum CacheCommand {
+    /// Every cached date with its versions
+    List,
+    /// Check that every cached file matches its checksum from the index
+    Verify,
+    /// Remove old files using the retention rules, or every date before --before
+    Prune {
+        #[arg(long, value_parser = parse_date)]
+        before: Option<NaiveDate>,
+        /// Only show what would be removed
+        #[arg(long)]
+        dry_run: bool,
+    },
+}
+
+fn parse_date(text: &str) -> Result<NaiveDate, String> {
+    Date::parse(text)
+        .and_then(|date| NaiveDate::from_ymd_opt(date.year, date.month, date.day))
+        .ok_or_else(|| format!("invalid date {:?}, use dd.mm.yyyy or yyyy-mm-dd", text))
+}
+
+// Run a subcommand other than serve, the result is the exit code
+pub async fn run(config: Config, command: Command) -> std::io::Result<i32> {
+    match command {
+        Command::Serve => unreachable!("serve is handled by main"),
+        Command::Fetch { date } => {
+            let Some((_lock, state)) = open_for_writing(config).await? else {
+                return Ok(1);
+            };
+            let date = match date.to_lowercase().as_str() {
+                "today" | "tomorrow" => resolve_when(&date),
+                _ => parse_date(&date)
+                    .map(Date::from)
+                    .map_err(ZastepstwaError::InvalidParameter),
+            };
+            let result = match date {
+                Ok(date) => fetch(&state, &date).await,
+                Err(e) => Err(e),
+            };
+            match result {
+                Ok(link) => {
+                    println!("{}", link);
+                    Ok(0)
+                }
+                Err(e) => {
+                    eprintln!("{} ({})", e, e.kind());
+                    Ok(1)
+                }
+            }
+        }
+        Command::Backfill { from, to, delay_ms } => {
+            let Some((_lock, state)) = open_for_writing(config).await? else {
+                return Ok(1);
+            };
+            backfill(&state, from, to, delay_ms).await
+        }
+        // These only read the saved index, so they don't need the lock and work next to the server
+        Command::Cache { command } => match command {
+            CacheCommand::List => {
+                list(&Index::read(config.cache_index()).await?);
+                Ok(0)
+            }
+            CacheCommand::Verify => {
+                verify(&config, &Index::read(config.cache_index()).await?).await
+            }
+            CacheCommand::Prune {
+                before,
+                dry_run: true,
+            } => {
+                let index = Index::read(config.cache_index()).await?;
+                prune(&config, &index, before, true).await
+            }
+            CacheCommand::Prune {
+                before,
+                dry_run: false,
+            } => {
+                let Some((_lock, state)) = open_for_writing(config).await? else {
+                    return Ok(1);
+                };
+                prune(&state.config, &state.index, before, false).await
+            }
+        },
+    }
+}
+
+// The state for commands that change the cache, None if the server or another command is using it.
+// The server keeps the index in memory and would overwrite what these commands change, see cache::lock
+async fn open_for_writing(config: Config) -> std::io::Result<Option<(CacheLock, AppState)>> {
+    let Some(lock) = cache::lock(&config.cache.dir)? else {
+        eprintln!(
+            "{} is used by the server or another command, stop it first",
+            config.cache.dir.display()
+        );
+        return Ok(None);
+    };
+    // Nobody else can be writing now, so every temp file is left over from a crash
+    cache::remove_temp_files(&config.cache.dir).await?;
+
+    // The tools are for whoever runs the server, maintenance mode is only for the users
+    let maintenance = MaintenanceState::inactive(&config);
+    let state = AppState::open(config, maintenance).await?;
+    Ok(Some((lock, state)))
+}
+
+// Always asks the school website, like the prefetcher does, and isn't counted as a hit
+async fn fetch(state: &AppState, date: &Date) -> Result<String, ZastepstwaError> {
+    let date = state::school_date(&state.config.school.holidays, date)?;
+    state.refresh(date).await
+}
+
+async fn backfill(
+    state: &AppState,
+    from: NaiveDate,
+    to: NaiveDate,
+    delay_ms: u64,
+) -> std::io::Result<i32> {
+    let (mut stored, mut missing, mut failed) = (0, 0, 0);
+    let mut date = from;
+    while date <= to {
+        if calendar::is_school_day(&state.config.school.holidays, date) {
+            let name = date.format("%d.%m.%Y");
+            // Dates checked recently are skipped, only requests to the school website need the pause
+            let result = match state.fresh_link(date) {
+                Some(link) => Ok(link),
+                None => {
+                    let result = state.refresh(date).await;
+                    tokio::time::sleep(std::time::Duration::from_millis(delay_ms)).await;
+                    result
+                }
+            };
+            match result {
+                Ok(_) => {
+                    println!("{} ok", name);
+                    stored += 1;
+                }
+                Err(ZastepstwaError::NotPublished(_)) => {
+                    println!("{} not published", name);
+                    missing += 1;
+                }
+                Err(e) => {
+                    println!("{} error: {} ({})", name, e, e.kind());
+                    failed += 1;
+                }
+            }
+        }
+        date += Duration::days(1);
+    }
+    println!(
+        "{} stored, {} not published, {} failed",
+        stored, missing, failed
+    );
+    Ok(if failed > 0 { 1 } else { 0 })
+}
+
+fn list(index: &Index) {
+    for (date, entry) in index.entries() {
+        println!(
+            "{}  {} B  {} version(s)  checked {}  {:?}  {} hit(s)",
+            date.format("%d.%m.%Y"),
+            entry.size,
+            entry.revisions,
+            entry.last_checked.format("%Y-%m-%d %H:%M"),
+            entry.upstream_status,
+            entry.hits
+        );
+    }
+}
+
+// Does the file exist and have the expected content?
+async fn check(path: &std::path::Path, sha256: &str) -> Option<String> {
+    match tokio::fs::read(path).await {
+        Ok(bytes) if sha256_hex(&bytes) == sha256 => None,
+        Ok(_) => Some(format!("{} doesn't match its checksum", path.display())),
+        Err(e) => Some(format!("{}: {}", path.display(), e)),
+    }
+}
+
+async fn verify(config: &Config, index: &Index) -> std::io::Result<i32> {
+    let mut problems = Vec::new();
+    let mut files = 0;
+    for (date, entry) in index.entries() {
+        let date = date.format("%d.%m.%Y").to_string();
+        files += 1;
+        problems.extend(check(&config.cached_pdf(&date), &entry.sha256).await);
+        for revision in &entry.history {
+            files += 1;
+            problems.extend(
+                check(
+                    &config.cached_revision(&date, revision.number),
+                    &revision.sha256,
+                )
+                .await,
+            );
+        }
+    }
+    for problem in &problems {
+        println!("{}", problem);
+    }
+    println!("{} file(s) checked, {} problem(s)", files, problems.len());
+    Ok(if problems.is_empty() { 0 } else { 1 })
+}
+
+// Without --before the retention rules from the config decide what goes
+async fn prune(
+    config: &Config,
+    index: &Index,
+    before: Option<NaiveDate>,
+    dry_run: bool,
+) -> std::io::Result<i32> {
+    let entries = index.entries();
+    let removals = match before {
+        Some(date) => retention::before(&entries, date),
+        None if config.retention.is_enabled() => {
+            retention::plan(&config.retention, &entries, Local::now().date_naive())
+        }
+        None => {
+            eprintln!("No retention rules are configured, use --before or the [retention] section");
+            return Ok(1);
+        }
+    };
+    for removal in &removals {
+        println!(
+            "{}{}",
+            if dry_run {
+                "would remove "
+            } else {
+                "removing "
+            },
+            removal
+        );
+    }
+    if !dry_run {
+        retention::apply(config, index, &removals).await?;
+    }
+    println!(
+        "{} item(s), {} B {}",
+        removals.len(),
+        removals.iter().map(|removal| removal.bytes()).sum::<u64>(),
+        if dry_run { "would be freed" } else { "freed" }
+    );
+    Ok(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn arguments() {
+        let cli = Cli::try_parse_from([
+            "zastepstwa-rust",
+            "backfill",
+            "--from",
+            "01.09.2022",
+            "--to",
+            "2022-09-30",
+        ])
+        .unwrap();
+        assert!(matches!(
+            cli.command,
+            Some(Command::Backfill { from, to, delay_ms: 500 })
+                if from == NaiveDate::from_ymd_opt(2022, 9, 1).unwrap()
+                    && to == NaiveDate::from_ymd_opt(2022, 9, 30).unwrap()
+        ));
+        assert!(Cli::try_parse_from(["zastepstwa-rust"])
+            .unwrap()
+            .command
+            .is_none());
+        assert!(Cli::try_parse_from([
+            "zastepstwa-rust",
+            "cache",
+            "prune",
+            "--before",
+            "31.02.2022"
+        ])
+        .is_err());
+    }
+}
@@ -0,0 +1,550 @@
+use chrono::NaiveDate;
+use serde::Deserialize;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use crate::cache;
+
+// Default location of the config file, can be changed with ZASTEPSTWA_CONFIG
+const DEFAULT_CONFIG_PATH: &str = "./zastepstwa.toml";
+// Prefix for all environment variable overrides
+const ENV_PREFIX: &str = "ZASTEPSTWA_";
+
+// Runtime configuration, loaded once at startup and shared with every handler
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub server: ServerConfig,
+    pub cache: CacheConfig,
+    pub upstream: UpstreamConfig,
+    pub maintenance: MaintenanceConfig,
+    pub admin: AdminConfig,
+    pub school: SchoolConfig,
+    pub prefetch: PrefetchConfig,
+    pub retention: RetentionConfig,
+    pub validation: ValidationConfig,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    // Address and port the HTTP server binds to
+    pub host: String,
+    pub port: u16,
+    // Public address used when building links to cached files
+    pub domain: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CacheConfig {
+    // Folder where downloaded PDFs are stored
+    pub dir: PathBuf,
+    // How long (in minutes) a cached PDF is considered fresh
+    pub time_min: i64,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct UpstreamConfig {
+    // Which kind of source the substitutions are fetched from
+    pub kind: SourceKind,
+    // Base URL of the school website, the file name ({dd.mm.yyyy}.pdf) is appended to it
+    pub url: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SourceKind {
+    // One PDF per day at {url}{dd.mm.yyyy}.pdf
+    PdfUrl,
+}
+
+impl std::str::FromStr for SourceKind {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "pdf_url" => Ok(SourceKind::PdfUrl),
+            _ => Err(()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MaintenanceConfig {
+    // Initial state, used until maintenance is changed through the admin API
+    pub enabled: bool,
+    pub message: String,
+    // Where the state set through the admin API is saved between restarts
+    pub state_file: PathBuf,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AdminConfig {
+    // Bearer token for the /admin endpoints, they are disabled if it isn't set
+    pub token: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SchoolConfig {
+    // Every room in the school, as it's written in the PDF (for example "12" or "sg"). Used to find free rooms.
+    pub rooms: Vec<String>,
+    // The normal plan: a CSV file, or a page or folder exported by Plan lekcji Optivum
+    pub timetable: Option<PathBuf>,
+    // Breaks and free days without lessons, on top of weekends and the winter break
+    pub holidays: Vec<Holiday>,
+}
+
+// Days without lessons, both dates included
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Holiday {
+    pub from: NaiveDate,
+    pub to: NaiveDate,
+}
+
+impl Holiday {
+    pub fn contains(&self, date: NaiveDate) -> bool {
+        (self.from..=self.to).contains(&date)
+    }
+}
+
+impl std::str::FromStr for Holiday {
+    type Err = ();
+
+    // "2024-12-23..2025-01-01" or a single day "2025-05-02"
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (from, to) = s.split_once("..").unwrap_or((s, s));
+        Ok(Holiday {
+            from: from.trim().parse().map_err(|_| ())?,
+            to: to.trim().parse().map_err(|_| ())?,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PrefetchConfig {
+    // Refresh today and the next school day in the background, more often when the school usually publishes
+    pub enabled: bool,
+}
+
+// Rules for removing old files from the cache. Without any of the limits nothing is removed.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RetentionConfig {
+    // Remove dates older than this many days
+    pub max_age_days: Option<u32>,
+    // Remove the oldest dates while the cache (with every version) is bigger than this
+    pub max_size_mb: Option<u64>,
+    // Never remove dates from the current school year (since the 1st of September)
+    pub keep_school_year: bool,
+    // Keep only this many newest versions of every date
+    pub keep_revisions: Option<u32>,
+    // How often the server applies the rules
+    pub interval_hours: u64,
+}
+
+impl RetentionConfig {
+    pub fn is_enabled(&self) -> bool {
+        self.max_age_days.is_some() || self.max_size_mb.is_some() || self.keep_revisions.is_some()
+    }
+}
+
+impl Default for RetentionConfig {
+    fn default() -> Self {
+        RetentionConfig {
+            max_age_days: None,
+            max_size_mb: None,
+            keep_school_year: true,
+            keep_revisions: None,
+            interval_hours: 24,
+        }
+    }
+}
+
+// What a download has to look like before it replaces the cached copy
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ValidationConfig {
+    // Smallest accepted file in bytes, error pages and broken downloads are usually tiny
+    pub min_size: u64,
+    // Accepted Content-Type headers, a missing header is always accepted
+    pub content_types: Vec<String>,
+    pub min_pages: u32,
+    pub max_pages: Option<u32>,
+}
+
+impl Default for ValidationConfig {
+    fn default() -> Self {
+        ValidationConfig {
+            min_size: 1024,
+            content_types: vec![
+                "application/pdf".to_string(),
+                "application/x-pdf".to_string(),
+                "application/octet-stream".to_string(),
+                "binary/octet-stream".to_string(),
+            ],
+            min_pages: 1,
+            max_pages: None,
+        }
+    }
+}
+
+impl Default for PrefetchConfig {
+    fn default() -> Self {
+        PrefetchConfig { enabled: true }
+    }
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        ServerConfig {
+            host: "0.0.0.0".to_string(),
+            port: 5000,
+            domain: "https://zastepstwa.ducky.pics".to_string(),
+        }
+    }
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        CacheConfig {
+            dir: PathBuf::from("./cached"),
+            time_min: 30,
+        }
+    }
+}
+
+impl Default for UpstreamConfig {
+    fn default() -> Self {
+        UpstreamConfig {
+            kind: SourceKind::PdfUrl,
+            url: "https://zastepstwa.zschie.pl/pliki/".to_string(),
+        }
+    }
+}
+
+impl Default for MaintenanceConfig {
+    fn default() -> Self {
+        MaintenanceConfig {
+            enabled: true,
+            message: "Skrót można usunąć - będzie niedługo aplikacja".to_string(),
+            state_file: PathBuf::from("./maintenance.json"),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    // The config file couldn't be read
+    Io(PathBuf, std::io::Error),
+    // The config file isn't valid TOML or has unknown/mistyped keys
+    Parse(PathBuf, toml::de::Error),
+    // An environment variable has a value that can't be parsed
+    Env(String, String),
+    // The configuration was parsed, but a value doesn't make sense
+    Invalid(String),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Io(path, err) => {
+                write!(f, "couldn't read config file {}: {}", path.display(), err)
+            }
+            ConfigError::Parse(path, err) => {
+                write!(f, "invalid config file {}: {}", path.display(), err)
+            }
+            ConfigError::Env(name, value) => {
+                write!(
+                    f,
+                    "invalid value {:?} for environment variable {}",
+                    value, name
+                )
+            }
+            ConfigError::Invalid(reason) => write!(f, "invalid configuration: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+impl Config {
+    // Load the config file (if there is one), apply environment overrides and validate the result.
+    // The file is optional unless its path was given explicitly with ZASTEPSTWA_CONFIG.
+    pub fn load() -> Result<Config, ConfigError> {
+        let (path, required) = match std::env::var(format!("{}CONFIG", ENV_PREFIX)) {
+            Ok(path) => (PathBuf::from(path), true),
+            Err(_) => (PathBuf::from(DEFAULT_CONFIG_PATH), false),
+        };
+
+        let mut config = if required || path.exists() {
+            Config::from_file(&path)?
+        } else {
+            Config::default()
+        };
+        config.apply_env()?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    pub fn from_file(path: &Path) -> Result<Config, ConfigError> {
+        let contents =
+            std::fs::read_to_string(path).map_err(|e| ConfigError::Io(path.to_path_buf(), e))?;
+        toml::from_str(&contents).map_err(|e| ConfigError::Parse(path.to_path_buf(), e))
+    }
+
+    // Override values with ZASTEPSTWA_* environment variables
+    fn apply_env(&mut self) -> Result<(), ConfigError> {
+        let var = |name: &str| {
+            let name = format!("{}{}", ENV_PREFIX, name);
+            std::env::var(&name).ok().map(|value| (name, value))
+        };
+
+        if let Some((_, value)) = var("HOST") {
+            self.server.host = value;
+        }
+        if let Some((name, value)) = var("PORT") {
+            self.server.port = parse_env(name, value)?;
+        }
+        if let Some((_, value)) = var("DOMAIN") {
+            self.server.domain = value;
+        }
+        if let Some((_, value)) = var("CACHE_DIR") {
+            self.cache.dir = PathBuf::from(value);
+        }
+        if let Some((name, value)) = var("CACHE_TIME_MIN") {
+            self.cache.time_min = parse_env(name, value)?;
+        }
+        if let Some((name, value)) = var("UPSTREAM_KIND") {
+            self.upstream.kind = parse_env(name, value)?;
+        }
+        if let Some((_, value)) = var("UPSTREAM_URL") {
+            self.upstream.url = value;
+        }
+        if let Some((name, value)) = var("MAINTENANCE") {
+            self.maintenance.enabled = parse_bool(name, value)?;
+        }
+        if let Some((_, value)) = var("MAINTENANCE_MESSAGE") {
+            self.maintenance.message = value;
+        }
+        if let Some((_, value)) = var("MAINTENANCE_STATE_FILE") {
+            self.maintenance.state_file = PathBuf::from(value);
+        }
+        if let Some((_, value)) = var("ADMIN_TOKEN") {
+            self.admin.token = Some(value);
+        }
+        if let Some((_, value)) = var("ROOMS") {
+            self.school.rooms = value
+                .split(',')
+                .map(|room| room.trim().to_string())
+                .filter(|room| !room.is_empty())
+                .collect();
+        }
+        if let Some((_, value)) = var("TIMETABLE") {
+            self.school.timetable = Some(PathBuf::from(value));
+        }
+        if let Some((name, value)) = var("HOLIDAYS") {
+            self.school.holidays = value
+                .split(',')
+                .filter(|holiday| !holiday.trim().is_empty())
+                .map(|holiday| parse_env(name.clone(), holiday.to_string()))
+                .collect::<Result<_, _>>()?;
+        }
+        if let Some((name, value)) = var("PREFETCH") {
+            self.prefetch.enabled = parse_bool(name, value)?;
+        }
+        if let Some((name, value)) = var("RETENTION_MAX_AGE_DAYS") {
+            self.retention.max_age_days = Some(parse_env(name, value)?);
+        }
+        if let Some((name, value)) = var("RETENTION_MAX_SIZE_MB") {
+            self.retention.max_size_mb = Some(parse_env(name, value)?);
+        }
+        if let Some((name, value)) = var("RETENTION_KEEP_SCHOOL_YEAR") {
+            self.retention.keep_school_year = parse_bool(name, value)?;
+        }
+        if let Some((name, value)) = var("RETENTION_KEEP_REVISIONS") {
+            self.retention.keep_revisions = Some(parse_env(name, value)?);
+        }
+        if let Some((name, value)) = var("RETENTION_INTERVAL_HOURS") {
+            self.retention.interval_hours = parse_env(name, value)?;
+        }
+        if let Some((name, value)) = var("VALIDATION_MIN_SIZE") {
+            self.validation.min_size = parse_env(name, value)?;
+        }
+        if let Some((_, value)) = var("VALIDATION_CONTENT_TYPES") {
+            self.validation.content_types = value
+                .split(',')
+                .map(|content_type| content_type.trim().to_string())
+                .filter(|content_type| !content_type.is_empty())
+                .collect();
+        }
+        if let Some((name, value)) = var("VALIDATION_MIN_PAGES") {
+            self.validation.min_pages = parse_env(name, value)?;
+        }
+        if let Some((name, value)) = var("VALIDATION_MAX_PAGES") {
+            self.validation.max_pages = Some(parse_env(name, value)?);
+        }
+        Ok(())
+    }
+
+    fn validate(&self) -> Result<(), ConfigError> {
+        if self.server.host.is_empty() {
+            return Err(ConfigError::Invalid(
+                "server.host can't be empty".to_string(),
+            ));
+        }
+        if !is_http_url(&self.server.domain) {
+            return Err(ConfigError::Invalid(format!(
+                "server.domain must start with http:// or https:// (got {:?})",
+                self.server.domain
+            )));
+        }
+        if self.server.domain.ends_with('/') {
+            return Err(ConfigError::Invalid(
+                "server.domain can't end with a slash".to_string(),
+            ));
+        }
+        if self.cache.time_min < 0 {
+            return Err(ConfigError::Invalid(
+                "cache.time_min can't be negative".to_string(),
+            ));
+        }
+        if self.cache.dir.as_os_str().is_empty() {
+            return Err(ConfigError::Invalid("cache.dir can't be empty".to_string()));
+        }
+        if self.maintenance.state_file.as_os_str().is_empty() {
+            return Err(ConfigError::Invalid(
+                "maintenance.state_file can't be empty".to_string(),
+            ));
+        }
+        if self
+            .admin
+            .token
+            .as_deref()
+            .is_some_and(|token| token.trim().is_empty())
+        {
+            return Err(ConfigError::Invalid(
+                "admin.token can't be empty".to_string(),
+            ));
+        }
+        if self.school.rooms.iter().any(|room| room.trim().is_empty()) {
+            return Err(ConfigError::Invalid(
+                "school.rooms can't contain empty names".to_string(),
+            ));
+        }
+        if let Some(holiday) = self
+            .school
+            .holidays
+            .iter()
+            .find(|holiday| holiday.from > holiday.to)
+        {
+            return Err(ConfigError::Invalid(format!(
+                "school.holidays: {} is after {}",
+                holiday.from, holiday.to
+            )));
+        }
+        if self.retention.max_size_mb == Some(0) {
+            return Err(ConfigError::Invalid(
+                "retention.max_size_mb must be at least 1".to_string(),
+            ));
+        }
+        if self.retention.keep_revisions == Some(0) {
+            return Err(ConfigError::Invalid(
+                "retention.keep_revisions must be at least 1".to_string(),
+            ));
+        }
+        if self.retention.interval_hours == 0 {
+            return Err(ConfigError::Invalid(
+                "retention.interval_hours must be at least 1".to_string(),
+            ));
+        }
+        if self.validation.content_types.is_empty() {
+            return Err(ConfigError::Invalid(
+                "validation.content_types can't be empty".to_string(),
+            ));
+        }
+        if self
+            .validation
+            .max_pages
+            .is_some_and(|max| max < self.validation.min_pages.max(1))
+        {
+            return Err(ConfigError::Invalid(
+                "validation.max_pages can't be smaller than validation.min_pages".to_string(),
+            ));
+        }
+        if !is_http_url(&self.upstream.url) {
+            return Err(ConfigError::Invalid(format!(
+                "upstream.url must start with http:// or https:// (got {:?})",
+                self.upstream.url
+            )));
+        }
+        if !self.upstream.url.ends_with('/') {
+            return Err(ConfigError::Invalid(
+                "upstream.url must end with a slash".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    // Public link to a cached file, for example https://zastepstwa.ducky.pics/files/10.10.2022.pdf
+    pub fn file_link(&self, date: &str) -> String {
+        format!("{}/files/{}.pdf", self.server.domain, date)
+    }
+
+    // The domain without the scheme and port, used in identifiers of feed entries and calendar events
+    pub fn host(&self) -> &str {
+        let domain = self.server.domain.as_str();
+        let host = domain.split_once("://").map_or(domain, |(_, host)| host);
+        host.split([':', '/']).next().unwrap_or(host)
+    }
+
+    // Path of the cached PDF for a date in the dd.mm.yyyy format
+    pub fn cached_pdf(&self, date: &str) -> PathBuf {
+        cache::pdf_path(&self.cache.dir, date)
+    }
+
+    // Path of an older version of the PDF for a date
+    pub fn cached_revision(&self, date: &str, number: u32) -> PathBuf {
+        cache::revision_path(&self.cache.dir, date, number)
+    }
+
+    // Folder with every version of a date
+    pub fn cached_revisions(&self, date: &str) -> PathBuf {
+        cache::revisions_dir(&self.cache.dir, date)
+    }
+
+    // Public link to an older version of a file, for example https://zastepstwa.ducky.pics/files/10.10.2022/2.pdf
+    pub fn revision_link(&self, date: &str, number: u32) -> String {
+        format!("{}/files/{}/{}.pdf", self.server.domain, date, number)
+    }
+
+    // Metadata of every cached date
+    pub fn cache_index(&self) -> PathBuf {
+        self.cache.dir.join("index.json")
+    }
+}
+
+fn parse_env<T: std::str::FromStr>(name: String, value: String) -> Result<T, ConfigError> {
+    value
+        .trim()
+        .parse()
+        .map_err(|_| ConfigError::Env(name, value))
+}
+
+fn parse_bool(name: String, value: String) -> Result<bool, ConfigError> {
+    match value.trim().to_lowercase().as_str() {
+        "1" | "true" | "yes" | "on" => Ok(true),
+        "0" | "false" | "no" | "off" => Ok(false),
+        _ => Err(ConfigError::Env(name, value)),
+    }
+}
+
+fn is_http_url(url: &str) -> bool {
+    url.starts_with("http://") || url.starts_with("https://")
+}
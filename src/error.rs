@@ -0,0 +1,150 @@
+use actix_web::http::StatusCode;
+use actix_web::{HttpResponse, ResponseError};
+use serde::Serialize;
+use std::fmt;
+
+// Every error a route can return. The JSON body keeps the old {code, error} shape
+// and adds a stable "kind" that clients can match on instead of the Polish message.
+#[derive(Debug, Clone)]
+pub enum ZastepstwaError {
+    // The date doesn't exist (for example 31.02)
+    InvalidDate,
+    // The date falls on a weekend, the message depends on how the date was chosen
+    Weekend(&'static str),
+    // The date falls on a school break
+    Holiday(&'static str),
+    // The school hasn't published substitutions for this date (yet), holds the date as dd.mm.yyyy
+    NotPublished(String),
+    // Something we keep ourselves (like an older version of a file) doesn't exist, holds the message to show
+    NotFound(String),
+    // The school website couldn't be reached
+    UpstreamOffline,
+    // The school website answered with a status we don't know what to do with
+    UpstreamStatus(u16),
+    // The school website sent something that isn't a valid substitution PDF and there's no older copy
+    InvalidDocument,
+    // Maintenance mode is on, holds the message to show
+    Maintenance(String),
+    // A query parameter is missing or has a wrong value
+    InvalidParameter(String),
+    // The request body couldn't be parsed
+    InvalidBody(String),
+    // Missing or wrong admin token
+    Unauthorized,
+    // The admin API is disabled because no token is configured
+    AdminDisabled,
+    // Reading or writing the cache on our disk failed (the details are logged where it happened)
+    Storage,
+    // The PDF was downloaded, but the substitution table couldn't be read from it
+    ParseFailed,
+    // Something went wrong on our side, holds the message to show
+    Internal(String),
+}
+
+// JSON body of every error
+#[derive(Debug, Serialize)]
+pub struct ErrorResponse {
+    pub code: u16,
+    pub error: String,
+    pub kind: &'static str,
+}
+
+impl ZastepstwaError {
+    // Machine-readable name of the error, these must never change
+    pub fn kind(&self) -> &'static str {
+        match self {
+            ZastepstwaError::InvalidDate => "invalid_date",
+            ZastepstwaError::Weekend(_) => "weekend",
+            ZastepstwaError::Holiday(_) => "holiday",
+            ZastepstwaError::NotPublished(_) => "not_published",
+            ZastepstwaError::NotFound(_) => "not_found",
+            ZastepstwaError::UpstreamOffline => "upstream_offline",
+            ZastepstwaError::UpstreamStatus(_) => "upstream_unexpected_status",
+            ZastepstwaError::InvalidDocument => "upstream_invalid_document",
+            ZastepstwaError::Maintenance(_) => "maintenance",
+            ZastepstwaError::InvalidParameter(_) => "invalid_parameter",
+            ZastepstwaError::InvalidBody(_) => "invalid_body",
+            ZastepstwaError::Unauthorized => "unauthorized",
+            ZastepstwaError::AdminDisabled => "admin_disabled",
+            ZastepstwaError::Storage => "storage",
+            ZastepstwaError::ParseFailed => "parse_failed",
+            ZastepstwaError::Internal(_) => "internal",
+        }
+    }
+}
+
+impl fmt::Display for ZastepstwaError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ZastepstwaError::InvalidDate => write!(f, "Nieprawidłowa data!"),
+            ZastepstwaError::Weekend(message) | ZastepstwaError::Holiday(message) => {
+                write!(f, "{}", message)
+            }
+            ZastepstwaError::NotPublished(date) => write!(
+                f,
+                "Nie ma zastępstw na dzień {}. Spróbuj ponownie później!",
+                date
+            ),
+            ZastepstwaError::UpstreamOffline => {
+                write!(f, "Strona szkoły jest offline! Spróbuj ponownie później!")
+            }
+            ZastepstwaError::UpstreamStatus(status) => write!(
+                f,
+                "Server zwrócił nieznany status {}. Spróbuj ponownie później!",
+                status
+            ),
+            ZastepstwaError::InvalidDocument => write!(
+                f,
+                "Strona szkoły zwróciła nieprawidłowy plik zamiast PDF-a. Spróbuj ponownie później!"
+            ),
+            ZastepstwaError::Maintenance(message)
+            | ZastepstwaError::InvalidParameter(message)
+            | ZastepstwaError::InvalidBody(message)
+            | ZastepstwaError::NotFound(message)
+            | ZastepstwaError::Internal(message) => write!(f, "{}", message),
+            ZastepstwaError::Unauthorized => write!(f, "Nieprawidłowy token"),
+            ZastepstwaError::AdminDisabled => write!(f, "API administracyjne jest wyłączone"),
+            ZastepstwaError::Storage => write!(
+                f,
+                "Błąd serwera przy zapisie lub odczycie pliku. Spróbuj ponownie później!"
+            ),
+            ZastepstwaError::ParseFailed => write!(
+                f,
+                "Nie udało się odczytać zastępstw z pliku PDF. Plik nadal jest dostępny pod linkiem!"
+            ),
+        }
+    }
+}
+
+impl ResponseError for ZastepstwaError {
+    // The status codes are the same ones the "code" field always had
+    fn status_code(&self) -> StatusCode {
+        match self {
+            ZastepstwaError::InvalidDate
+            | ZastepstwaError::Weekend(_)
+            | ZastepstwaError::Holiday(_)
+            | ZastepstwaError::InvalidParameter(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            ZastepstwaError::NotPublished(_)
+            | ZastepstwaError::NotFound(_)
+            | ZastepstwaError::UpstreamStatus(_) => StatusCode::NOT_FOUND,
+            ZastepstwaError::UpstreamOffline
+            | ZastepstwaError::InvalidDocument
+            | ZastepstwaError::Maintenance(_)
+            | ZastepstwaError::Storage
+            | ZastepstwaError::ParseFailed
+            | ZastepstwaError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            ZastepstwaError::InvalidBody(_) => StatusCode::BAD_REQUEST,
+            ZastepstwaError::Unauthorized => StatusCode::UNAUTHORIZED,
+            ZastepstwaError::AdminDisabled => StatusCode::NOT_FOUND,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let status = self.status_code();
+        HttpResponse::build(status).json(ErrorResponse {
+            code: status.as_u16(),
+            error: self.to_string(),
+            kind: self.kind(),
+        })
+    }
+}
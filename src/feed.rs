@@ -0,0 +1,264 @@
+use actix_web::{get, web, HttpResponse};
+use chrono::{DateTime, Local, NaiveDate};
+use serde::Deserialize;
+
+use crate::changes::{diff, Changes};
+use crate::config::Config;
+use crate::ical::class_title;
+use crate::parser::Substitution;
+use crate::plan::escape;
+use crate::state::AppState;
+
+// How many of the newest versions are in a feed
+const ENTRIES: usize = 50;
+// How many of the newest versions a class feed looks through, so a class that rarely changes
+// doesn't make every request parse the whole cache
+const LOOK_BACK: usize = 200;
+
+// One stored version of the PDF for a date
+struct FeedEntry {
+    date: NaiveDate,
+    revision: u32,
+    fetched_at: DateTime<Local>,
+    // What changed compared to the previous version, if both are kept and could be parsed
+    summary: Option<String>,
+}
+
+// "3TI gr. 1, 2. informatyka - zastępstwo (Anna Nowak)"
+fn line(record: &Substitution) -> String {
+    match &record.group {
+        Some(group) => format!("{} gr. {}, {}", record.class, group, class_title(record)),
+        None => format!("{}, {}", record.class, class_title(record)),
+    }
+}
+
+// Short Polish description of a new version, the first version lists everything
+pub fn summarize(changes: &Changes, first: bool) -> String {
+    if changes.is_empty() {
+        return "Bez zmian w zastępstwach.".to_string();
+    }
+    let mut lines = Vec::new();
+    if first {
+        lines.push(format!("Zastępstw: {}", changes.added.len()));
+        lines.extend(changes.added.iter().map(line));
+        return lines.join("\n");
+    }
+    lines.push(format!(
+        "Dodane: {}, usunięte: {}, zmienione: {}",
+        changes.added.len(),
+        changes.removed.len(),
+        changes.modified.len()
+    ));
+    lines.extend(
+        changes
+            .added
+            .iter()
+            .map(|record| format!("+ {}", line(record))),
+    );
+    lines.extend(
+        changes
+            .removed
+            .iter()
+            .map(|record| format!("- {}", line(record))),
+    );
+    lines.extend(
+        changes
+            .modified
+            .iter()
+            .map(|change| format!("* {}", line(&change.after))),
+    );
+    lines.join("\n")
+}
+
+fn render(config: &Config, title: &str, path: &str, entries: &[FeedEntry]) -> String {
+    let host = config.host();
+    let updated = entries
+        .iter()
+        .map(|entry| entry.fetched_at)
+        .max()
+        .unwrap_or_else(Local::now);
+    let mut feed = format!(
+        r#"<?xml version="1.0" encoding="utf-8"?>
+<feed xmlns="http://www.w3.org/2005/Atom">
+<title>{title}</title>
+<id>tag:{host},2022:{path}</id>
+<link rel="self" href="{domain}{path}"/>
+<updated>{updated}</updated>
+<author><name>zastepstwa-rust</name></author>
+"#,
+        title = escape(title),
+        host = escape(host),
+        path = escape(path),
+        domain = escape(&config.server.domain),
+        updated = updated.to_rfc3339(),
+    );
+    for entry in entries {
+        let date = entry.date.format("%d.%m.%Y").to_string();
+        let title = if entry.revision == 1 {
+            format!("Zastępstwa na {}", date)
+        } else {
+            format!("Zmiana zastępstw na {} (wersja {})", date, entry.revision)
+        };
+        let summary = entry
+            .summary
+            .as_deref()
+            .unwrap_or("Brak listy zmian dla tej wersji.");
+        feed.push_str(&format!(
+            r#"<entry>
+<title>{title}</title>
+<id>tag:{host},2022:{date}/{revision}</id>
+<link rel="alternate" type="application/pdf" href="{revision_link}"/>
+<link rel="related" type="application/pdf" href="{file_link}"/>
+<updated>{updated}</updated>
+<content type="text">{summary}</content>
+</entry>
+"#,
+            title = escape(&title),
+            host = escape(host),
+            date = date,
+            revision = entry.revision,
+            revision_link = escape(&config.revision_link(&date, entry.revision)),
+            file_link = escape(&config.file_link(&date)),
+            updated = entry.fetched_at.to_rfc3339(),
+            summary = escape(summary),
+        ));
+    }
+    feed.push_str("</feed>\n");
+    feed
+}
+
+// The newest versions of every date, with their change summaries. With a class only versions that changed
+// something for that class are kept.
+async fn entries(state: &AppState, class: Option<(&str, Option<&str>)>) -> Vec<FeedEntry> {
+    // (date, version, the version before it if it's still kept, when it was downloaded)
+    let mut revisions: Vec<(NaiveDate, u32, Option<u32>, DateTime<Local>)> = Vec::new();
+    for (date, entry) in state.index.entries() {
+        let mut previous = None;
+        for revision in entry.history {
+            revisions.push((date, revision.number, previous, revision.fetched_at));
+            previous = Some(revision.number);
+        }
+    }
+    revisions.sort_by_key(|revision| std::cmp::Reverse(revision.3));
+    revisions.truncate(LOOK_BACK);
+
+    let mut entries = Vec::new();
+    for (date, revision, previous, fetched_at) in revisions {
+        if entries.len() == ENTRIES {
+            break;
+        }
+        let name = date.format("%d.%m.%Y").to_string();
+        let new = state
+            .parsed
+            .read(&state.config.cached_revision(&name, revision))
+            .await;
+        let old = match (revision, previous) {
+            (1, _) => Ok(Default::default()),
+            (_, Some(previous)) => {
+                state
+                    .parsed
+                    .read(&state.config.cached_revision(&name, previous))
+                    .await
+            }
+            // The previous version was removed by the retention rules, there's nothing to compare with
+            (_, None) => Err(crate::error::ZastepstwaError::NotFound(String::new())),
+        };
+        let changes = match (old, new) {
+            (Ok(old), Ok(new)) => Some(diff(&old, &new)),
+            _ => None,
+        };
+        let changes = match (changes, class) {
+            (Some(changes), Some((class, group))) => {
+                let changes = changes.for_class(class, group);
+                if changes.is_empty() {
+                    continue;
+                }
+                Some(changes)
+            }
+            (changes, _) => changes,
+        };
+        entries.push(FeedEntry {
+            date,
+            revision,
+            fetched_at,
+            summary: changes.map(|changes| summarize(&changes, revision == 1)),
+        });
+    }
+    entries
+}
+
+fn atom(body: String) -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type("application/atom+xml; charset=utf-8")
+        .body(body)
+}
+
+// Every new or changed PDF
+// /feed.atom
+#[get("/feed.atom")]
+async fn atom_feed(state: web::Data<AppState>) -> HttpResponse {
+    let entries = entries(&state, None).await;
+    atom(render(&state.config, "Zastępstwa", "/feed.atom", &entries))
+}
+
+#[derive(Deserialize)]
+struct ClassFeedQuery {
+    group: Option<String>,
+}
+
+// Only versions that changed something for one class
+// /feed/3TI.atom
+// /feed/3TI.atom?group=1
+#[get("/feed/{class}.atom")]
+async fn class_feed(
+    state: web::Data<AppState>,
+    class: web::Path<String>,
+    query: web::Query<ClassFeedQuery>,
+) -> HttpResponse {
+    let group = query.group.as_deref();
+    let entries = entries(&state, Some((&class, group))).await;
+    let title = match group {
+        Some(group) => format!("Zastępstwa {} gr. {}", class, group),
+        None => format!("Zastępstwa {}", class),
+    };
+    atom(render(
+        &state.config,
+        &title,
+        &format!("/feed/{}.atom", class),
+        &entries,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn entry_summaries() {
+        let records = crate::parser::parse(include_bytes!("../tests/fixtures/basic.pdf")).unwrap();
+        let first = summarize(&diff(&[], &records), true);
+        assert!(first.starts_with("Zastępstw: 7\n"));
+        assert!(first.contains("3TI gr. 1, 2. informatyka - zastępstwo"));
+
+        let mut changed = records.clone();
+        changed.remove(0);
+        let summary = summarize(&diff(&records, &changed), false);
+        assert!(summary.starts_with("Dodane: 0, usunięte: 1, zmienione: 0\n- 3TI, 1."));
+        assert_eq!(
+            summarize(&diff(&records, &records), false),
+            "Bez zmian w zastępstwach."
+        );
+
+        let config = Config::default();
+        let entries = [FeedEntry {
+            date: NaiveDate::from_ymd_opt(2022, 10, 10).unwrap(),
+            revision: 2,
+            fetched_at: Local::now(),
+            summary: Some("<b> & co".to_string()),
+        }];
+        let feed = render(&config, "Zastępstwa", "/feed.atom", &entries);
+        assert!(feed.contains("<title>Zmiana zastępstw na 10.10.2022 (wersja 2)</title>"));
+        assert!(feed.contains("/files/10.10.2022/2.pdf\"/>"));
+        assert!(feed.contains("&lt;b&gt; &amp; co"));
+    }
+}
@@ -0,0 +1,351 @@
+use actix_web::{get, web, HttpResponse};
+use chrono::{DateTime, Duration, Local, NaiveDate, TimeZone, Utc};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use crate::calendar;
+use crate::error::ZastepstwaError;
+use crate::parser::{Kind, Substitution};
+use crate::state::AppState;
+use crate::teachers::teacher_matches;
+use crate::timetable::{Bell, Timetable};
+
+// How many calendar days before and after today the feeds cover
+const PAST_DAYS: i64 = 7;
+const UPCOMING_DAYS: i64 = 7;
+
+// The substitutions of one school day in the window
+struct Day {
+    date: NaiveDate,
+    // Calendar apps replace an event when its SEQUENCE goes up, the PDF revision always does
+    revision: u32,
+    changed: DateTime<Local>,
+    records: Arc<Vec<Substitution>>,
+}
+
+// Every school day in the window that has substitutions. Calendar apps poll often, so the feeds
+// only read the cache and never ask the school website. Today and the next school day are kept
+// fresh by the prefetcher, other dates by whoever asks for them.
+async fn window(state: &AppState) -> Vec<Day> {
+    let today = Local::now().date_naive();
+    let mut days = Vec::new();
+    for offset in -PAST_DAYS..=UPCOMING_DAYS {
+        let date = today + Duration::days(offset);
+        if !calendar::is_school_day(&state.config.school.holidays, date) {
+            continue;
+        }
+        // Nothing was published for this date (yet)
+        let Some(entry) = state.index.get(date) else {
+            continue;
+        };
+        let name = date.format("%d.%m.%Y").to_string();
+        let records = state.parsed.read(&state.config.cached_pdf(&name)).await;
+        // One broken day shouldn't take the whole calendar down, the reason is logged where it happened
+        let records = match records {
+            Ok(records) if !records.is_empty() => records,
+            _ => continue,
+        };
+        days.push(Day {
+            date,
+            revision: entry.revisions,
+            changed: entry.last_changed,
+            records,
+        });
+    }
+    days
+}
+
+// Text values can't contain raw commas, semicolons or line breaks
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace(';', "\\;")
+        .replace(',', "\\,")
+        .replace('\n', "\\n")
+}
+
+// Lines longer than 75 bytes are folded, continuation lines start with a space
+fn fold(line: &str) -> String {
+    let mut folded = String::new();
+    let mut length = 0;
+    for c in line.chars() {
+        if length + c.len_utf8() > 75 {
+            folded.push_str("\r\n ");
+            length = 1;
+        }
+        folded.push(c);
+        length += c.len_utf8();
+    }
+    folded.push_str("\r\n");
+    folded
+}
+
+fn utc(date: NaiveDate, time: chrono::NaiveTime) -> String {
+    let local = Local
+        .from_local_datetime(&date.and_time(time))
+        .earliest()
+        .map(|time| time.with_timezone(&Utc))
+        .unwrap_or_else(|| Utc.from_utc_datetime(&date.and_time(time)));
+    local.format("%Y%m%dT%H%M%SZ").to_string()
+}
+
+// The same lesson always gets the same UID, so a new version of the PDF updates the event instead of adding one
+fn uid(date: NaiveDate, record: &Substitution, host: &str) -> String {
+    let key = format!(
+        "{}-{}-{}-{}-{}",
+        date.format("%Y%m%d"),
+        record.lesson,
+        record.class,
+        record.group.as_deref().unwrap_or(""),
+        record.absent_teacher
+    );
+    let key: String = key
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '-' })
+        .collect();
+    format!("{}@{}", key, host)
+}
+
+fn describe(record: &Substitution) -> String {
+    let mut lines = vec![format!("Nieobecny: {}", record.absent_teacher)];
+    if let Some(teacher) = &record.substitute_teacher {
+        lines.push(format!("Zastępca: {}", teacher));
+    }
+    if let Some(note) = &record.note {
+        lines.push(format!("Uwagi: {}", note));
+    }
+    lines.join("\n")
+}
+
+// One event for one record, the title is picked by the feed
+fn event(
+    day: &Day,
+    record: &Substitution,
+    title: &str,
+    bells: &BTreeMap<u32, Bell>,
+    host: &str,
+) -> String {
+    let mut lines = vec![
+        "BEGIN:VEVENT".to_string(),
+        format!("UID:{}", uid(day.date, record, host)),
+        format!("SEQUENCE:{}", day.revision),
+        format!(
+            "DTSTAMP:{}",
+            day.changed.with_timezone(&Utc).format("%Y%m%dT%H%M%SZ")
+        ),
+    ];
+    match bells.get(&record.lesson) {
+        Some(bell) => {
+            lines.push(format!("DTSTART:{}", utc(day.date, bell.start)));
+            lines.push(format!("DTEND:{}", utc(day.date, bell.end)));
+        }
+        // Without the bell schedule the lesson number is all we know, so it's an all-day event
+        None => {
+            lines.push(format!("DTSTART;VALUE=DATE:{}", day.date.format("%Y%m%d")));
+            lines.push(format!(
+                "DTEND;VALUE=DATE:{}",
+                (day.date + Duration::days(1)).format("%Y%m%d")
+            ));
+        }
+    }
+    lines.push(format!("SUMMARY:{}", escape(title)));
+    lines.push(format!("DESCRIPTION:{}", escape(&describe(record))));
+    // A moved lesson takes place in the room from the note
+    let room = record.moved_to().or_else(|| record.room.clone());
+    if let Some(room) = room.filter(|_| record.kind != Kind::Cancelled) {
+        lines.push(format!("LOCATION:{}", escape(&format!("s. {}", room))));
+    }
+    if record.kind == Kind::Cancelled {
+        lines.push("STATUS:CANCELLED".to_string());
+    }
+    lines.push("END:VEVENT".to_string());
+    lines.iter().map(|line| fold(line)).collect()
+}
+
+fn calendar(name: &str, events: &[String]) -> String {
+    let mut calendar = String::new();
+    for line in [
+        "BEGIN:VCALENDAR",
+        "VERSION:2.0",
+        "PRODID:-//zastepstwa-rust//PL",
+        "CALSCALE:GREGORIAN",
+        "METHOD:PUBLISH",
+        &format!("X-WR-CALNAME:{}", escape(name)),
+    ] {
+        calendar.push_str(&fold(line));
+    }
+    for event in events {
+        calendar.push_str(event);
+    }
+    calendar.push_str(&fold("END:VCALENDAR"));
+    calendar
+}
+
+// "3. matematyka - zastępstwo (Anna Nowak)"
+pub fn class_title(record: &Substitution) -> String {
+    let subject = record.subject.as_deref().unwrap_or("lekcja");
+    let what = match record.kind {
+        Kind::Cancelled => "odwołana".to_string(),
+        Kind::Joined => "łączona".to_string(),
+        Kind::Moved => match record.moved_to() {
+            Some(room) => format!("przeniesiona do s. {}", room),
+            None => "przeniesiona".to_string(),
+        },
+        Kind::Substituted => match &record.substitute_teacher {
+            Some(teacher) => format!("zastępstwo ({})", teacher),
+            None => "zastępstwo".to_string(),
+        },
+    };
+    format!("{}. {} - {}", record.lesson, subject, what)
+}
+
+fn ics(body: String) -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type("text/calendar; charset=utf-8")
+        .body(body)
+}
+
+#[derive(Deserialize)]
+struct ClassFeedQuery {
+    group: Option<String>,
+}
+
+// Substitutions of one class for calendar apps
+// /ical/class/3TI.ics
+// /ical/class/3TI.ics?group=1
+#[get("/ical/class/{class}.ics")]
+async fn class_feed(
+    state: web::Data<AppState>,
+    timetable: web::Data<Timetable>,
+    class: web::Path<String>,
+    query: web::Query<ClassFeedQuery>,
+) -> Result<HttpResponse, ZastepstwaError> {
+    if let Some(message) = state.maintenance.active_message() {
+        return Err(ZastepstwaError::Maintenance(message));
+    }
+    let host = state.config.host();
+    let days = window(&state).await;
+    let mut events = Vec::new();
+    for day in &days {
+        for record in day.records.iter() {
+            if record.is_for(&class, query.group.as_deref()) {
+                events.push(event(
+                    day,
+                    record,
+                    &class_title(record),
+                    &timetable.bells,
+                    host,
+                ));
+            }
+        }
+    }
+    let name = match &query.group {
+        Some(group) => format!("Zastępstwa {} gr. {}", class, group),
+        None => format!("Zastępstwa {}", class),
+    };
+    Ok(ics(calendar(&name, &events)))
+}
+
+// Duties and absences of one teacher for calendar apps
+// /ical/teacher/Kowalski.ics
+// /ical/teacher/Jan%20Kowalski.ics
+#[get("/ical/teacher/{name}.ics")]
+async fn teacher_feed(
+    state: web::Data<AppState>,
+    timetable: web::Data<Timetable>,
+    name: web::Path<String>,
+) -> Result<HttpResponse, ZastepstwaError> {
+    if let Some(message) = state.maintenance.active_message() {
+        return Err(ZastepstwaError::Maintenance(message));
+    }
+    if name.trim().is_empty() {
+        return Err(ZastepstwaError::InvalidParameter(
+            "Nazwa nauczyciela nie może być pusta".to_string(),
+        ));
+    }
+    let host = state.config.host();
+    let days = window(&state).await;
+    let mut events = Vec::new();
+    for day in &days {
+        for record in day.records.iter() {
+            let subject = record.subject.as_deref().unwrap_or("lekcja");
+            let title = if record
+                .substitute_teacher
+                .as_deref()
+                .is_some_and(|teacher| teacher_matches(teacher, &name))
+            {
+                format!(
+                    "{}. Zastępstwo: {} {} za {}",
+                    record.lesson, record.class, subject, record.absent_teacher
+                )
+            } else if teacher_matches(&record.absent_teacher, &name) {
+                format!(
+                    "{}. {} {} - nieobecność, {}",
+                    record.lesson,
+                    record.class,
+                    subject,
+                    match &record.substitute_teacher {
+                        Some(teacher) => format!("zastępuje {}", teacher),
+                        None => "lekcja odwołana".to_string(),
+                    }
+                )
+            } else {
+                continue;
+            };
+            events.push(event(day, record, &title, &timetable.bells, host));
+        }
+    }
+    Ok(ics(calendar(&format!("Zastępstwa - {}", name), &events)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveTime;
+
+    #[test]
+    fn events() {
+        let records = crate::parser::parse(include_bytes!("../tests/fixtures/basic.pdf")).unwrap();
+        let date = NaiveDate::from_ymd_opt(2022, 10, 10).unwrap();
+        let day = Day {
+            date,
+            revision: 3,
+            changed: Local::now(),
+            records: Arc::new(records.clone()),
+        };
+        let bells = BTreeMap::from([(
+            1,
+            Bell {
+                start: NaiveTime::from_hms_opt(8, 0, 0).unwrap(),
+                end: NaiveTime::from_hms_opt(8, 45, 0).unwrap(),
+            },
+        )]);
+
+        // The UID doesn't depend on what changed in the record
+        let mut changed = records[0].clone();
+        changed.substitute_teacher = Some("Ktoś Inny".to_string());
+        assert_eq!(
+            uid(date, &records[0], "example.com"),
+            uid(date, &changed, "example.com")
+        );
+        assert_ne!(
+            uid(date, &records[0], "example.com"),
+            uid(date, &records[1], "example.com")
+        );
+
+        let first = event(&day, &records[0], &class_title(&records[0]), &bells, "x");
+        assert!(first.contains("SEQUENCE:3\r\n"));
+        assert!(first.contains(&format!("DTSTART:{}\r\n", utc(date, bells[&1].start))));
+        // No bell for lesson 3, so it's an all-day event, and a cancelled one
+        let cancelled = event(&day, &records[2], &class_title(&records[2]), &bells, "x");
+        assert!(cancelled.contains("DTSTART;VALUE=DATE:20221010\r\n"));
+        assert!(cancelled.contains("STATUS:CANCELLED\r\n"));
+        assert!(cancelled.lines().all(|line| line.len() <= 75));
+
+        assert_eq!(escape("a, b; c"), "a\\, b\\; c");
+        assert_eq!(
+            fold(&"x".repeat(80)),
+            format!("{}\r\n {}\r\n", "x".repeat(75), "x".repeat(5))
+        );
+    }
+}
@@ -0,0 +1,411 @@
+use chrono::{DateTime, Local, NaiveDate};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+use crate::cache;
+use crate::source::Validators;
+
+// How often hits are saved when nothing else changes
+const HITS_INTERVAL: std::time::Duration = std::time::Duration::from_secs(5 * 60);
+
+// What the school website said the last time we asked about a date
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum UpstreamStatus {
+    // A new copy was downloaded
+    Ok,
+    // The cached copy is still up to date
+    NotModified,
+    // The file isn't there (anymore)
+    NotPublished,
+    Offline,
+    UnexpectedStatus(u16),
+    // A download failed validation, the previous copy was kept
+    Rejected,
+}
+
+// Everything we know about one cached date
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Entry {
+    // When the date was downloaded for the first time
+    pub first_seen: DateTime<Local>,
+    // When we last asked the school website about it
+    pub last_checked: DateTime<Local>,
+    // When the content last changed
+    pub last_changed: DateTime<Local>,
+    pub upstream_status: UpstreamStatus,
+    // SHA-256 (hex) and size of the current copy
+    pub sha256: String,
+    pub size: u64,
+    // How many different versions we have seen
+    pub revisions: u32,
+    // Every version we have seen, oldest first
+    #[serde(default)]
+    pub history: Vec<Revision>,
+    // How many times the date was requested
+    pub hits: u64,
+    // Sent with the next refresh, so the server can answer "not modified"
+    #[serde(default)]
+    pub validators: Validators,
+}
+
+// One version of the PDF for a date, the file is kept in the revisions folder
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Revision {
+    // Counted from 1
+    pub number: u32,
+    pub sha256: String,
+    pub size: u64,
+    // When this version was downloaded
+    pub fetched_at: DateTime<Local>,
+}
+
+impl Entry {
+    // The copy is fresh if the last check went fine and was less than `time_min` minutes ago
+    pub fn is_fresh(&self, time_min: i64) -> bool {
+        matches!(
+            self.upstream_status,
+            UpstreamStatus::Ok | UpstreamStatus::NotModified
+        ) && (Local::now() - self.last_checked).num_minutes() < time_min
+    }
+}
+
+// Metadata of every cached date, saved as JSON next to the PDFs
+pub struct Index {
+    path: PathBuf,
+    entries: Mutex<BTreeMap<NaiveDate, Entry>>,
+    // Saves are done one at a time, so an older snapshot can't overwrite a newer one
+    save_lock: tokio::sync::Mutex<()>,
+    // Hits are only counted in memory, they're saved with the next change or by flush
+    unsaved_hits: AtomicBool,
+}
+
+impl Index {
+    // Load the index and bring it in line with the PDFs that are actually in the cache folder
+    pub async fn open(path: PathBuf, cache_dir: &Path) -> std::io::Result<Index> {
+        let mut entries = read_entries(&path).await?;
+
+        let files = cached_dates(cache_dir).await?;
+        // Forget dates whose PDF was removed by hand
+        entries.retain(|date, _| files.contains_key(date));
+        // Add PDFs that were cached before the index existed
+        for (date, file) in files {
+            if entries.contains_key(&date) {
+                continue;
+            }
+            let bytes = tokio::fs::read(&file).await?;
+            let modified = DateTime::from(tokio::fs::metadata(&file).await?.modified()?);
+            log::info!("Adding {} to the cache index", file.display());
+            entries.insert(
+                date,
+                Entry {
+                    first_seen: modified,
+                    last_checked: modified,
+                    last_changed: modified,
+                    upstream_status: UpstreamStatus::Ok,
+                    sha256: sha256_hex(&bytes),
+                    size: bytes.len() as u64,
+                    revisions: 1,
+                    history: Vec::new(),
+                    hits: 0,
+                    validators: Validators::default(),
+                },
+            );
+        }
+
+        // Keep the current copy as the first revision of dates cached before revisions were kept
+        for (date, entry) in entries.iter_mut() {
+            if !entry.history.is_empty() {
+                continue;
+            }
+            let date = date.format("%d.%m.%Y").to_string();
+            let revision = cache::revision_path(cache_dir, &date, 1);
+            cache::store(
+                &revision,
+                &tokio::fs::read(cache::pdf_path(cache_dir, &date)).await?,
+            )
+            .await?;
+            entry.revisions = 1;
+            entry.history.push(Revision {
+                number: 1,
+                sha256: entry.sha256.clone(),
+                size: entry.size,
+                fetched_at: entry.last_changed,
+            });
+        }
+
+        let index = Index {
+            path,
+            entries: Mutex::new(entries),
+            save_lock: tokio::sync::Mutex::new(()),
+            unsaved_hits: AtomicBool::new(false),
+        };
+        index.save().await?;
+        Ok(index)
+    }
+
+    // The index exactly as it was saved, for tools that only look at the cache. Nothing is
+    // added, migrated or written, so it's safe while the server runs.
+    pub async fn read(path: PathBuf) -> std::io::Result<Index> {
+        Ok(Index {
+            entries: Mutex::new(read_entries(&path).await?),
+            path,
+            save_lock: tokio::sync::Mutex::new(()),
+            unsaved_hits: AtomicBool::new(false),
+        })
+    }
+
+    pub fn get(&self, date: NaiveDate) -> Option<Entry> {
+        self.entries.lock().unwrap().get(&date).cloned()
+    }
+
+    pub fn entries(&self) -> Vec<(NaiveDate, Entry)> {
+        self.entries
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(date, entry)| (*date, entry.clone()))
+            .collect()
+    }
+
+    // Is the downloaded content different from what we have? Dates we don't know yet always are.
+    pub fn is_changed(&self, date: NaiveDate, bytes: &[u8]) -> bool {
+        self.get(date)
+            .is_none_or(|entry| entry.sha256 != sha256_hex(bytes))
+    }
+
+    // Number the next new version of a date will get
+    pub fn next_revision(&self, date: NaiveDate) -> u32 {
+        self.get(date).map_or(1, |entry| entry.revisions + 1)
+    }
+
+    // Remember a successful download, a new revision is only counted if the content changed
+    pub async fn record_download(
+        &self,
+        date: NaiveDate,
+        bytes: &[u8],
+        validators: Validators,
+    ) -> std::io::Result<()> {
+        let now = Local::now();
+        let sha256 = sha256_hex(bytes);
+        {
+            let mut entries = self.entries.lock().unwrap();
+            let entry = entries.entry(date).or_insert_with(|| Entry {
+                first_seen: now,
+                last_checked: now,
+                last_changed: now,
+                upstream_status: UpstreamStatus::Ok,
+                sha256: String::new(),
+                size: 0,
+                revisions: 0,
+                history: Vec::new(),
+                hits: 0,
+                validators: Validators::default(),
+            });
+            if entry.sha256 != sha256 {
+                entry.revisions += 1;
+                entry.history.push(Revision {
+                    number: entry.revisions,
+                    sha256: sha256.clone(),
+                    size: bytes.len() as u64,
+                    fetched_at: now,
+                });
+                entry.sha256 = sha256;
+                entry.size = bytes.len() as u64;
+                entry.last_changed = now;
+            }
+            entry.last_checked = now;
+            entry.upstream_status = UpstreamStatus::Ok;
+            entry.validators = validators;
+        }
+        self.save().await
+    }
+
+    // Remember the result of a check that didn't bring a new copy
+    pub async fn record_check(
+        &self,
+        date: NaiveDate,
+        status: UpstreamStatus,
+    ) -> std::io::Result<()> {
+        {
+            let mut entries = self.entries.lock().unwrap();
+            match entries.get_mut(&date) {
+                Some(entry) => {
+                    entry.last_checked = Local::now();
+                    entry.upstream_status = status;
+                }
+                // Dates without a cached copy aren't tracked
+                None => return Ok(()),
+            }
+        }
+        self.save().await
+    }
+
+    // Drop a date whose files were removed
+    pub async fn forget(&self, date: NaiveDate) -> std::io::Result<()> {
+        if self.entries.lock().unwrap().remove(&date).is_none() {
+            return Ok(());
+        }
+        self.save().await
+    }
+
+    // Drop versions whose files were removed, the numbering goes on as before
+    pub async fn forget_revisions(&self, date: NaiveDate, numbers: &[u32]) -> std::io::Result<()> {
+        match self.entries.lock().unwrap().get_mut(&date) {
+            Some(entry) => entry
+                .history
+                .retain(|revision| !numbers.contains(&revision.number)),
+            None => return Ok(()),
+        }
+        self.save().await
+    }
+
+    // Counted on every request, so it isn't written to disk right away
+    pub fn record_hit(&self, date: NaiveDate) {
+        if let Some(entry) = self.entries.lock().unwrap().get_mut(&date) {
+            entry.hits += 1;
+            self.unsaved_hits.store(true, Ordering::Relaxed);
+        }
+    }
+
+    // Save the hits counted since the last save, if there are any
+    pub async fn flush(&self) -> std::io::Result<()> {
+        if !self.unsaved_hits.load(Ordering::Relaxed) {
+            return Ok(());
+        }
+        self.save().await
+    }
+
+    async fn save(&self) -> std::io::Result<()> {
+        let _guard = self.save_lock.lock().await;
+        // Hits counted while this snapshot is written set the flag again
+        self.unsaved_hits.store(false, Ordering::Relaxed);
+        let json = serde_json::to_vec_pretty(&*self.entries.lock().unwrap())?;
+        let result = cache::store(&self.path, &json).await;
+        if result.is_err() {
+            self.unsaved_hits.store(true, Ordering::Relaxed);
+        }
+        result
+    }
+}
+
+async fn read_entries(path: &Path) -> std::io::Result<BTreeMap<NaiveDate, Entry>> {
+    match tokio::fs::read(path).await {
+        Ok(contents) => Ok(serde_json::from_slice(&contents)?),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(BTreeMap::new()),
+        Err(e) => Err(e),
+    }
+}
+
+// Saves the hits every few minutes while the server runs
+pub async fn flush_hits(index: &Index) {
+    loop {
+        tokio::time::sleep(HITS_INTERVAL).await;
+        if let Err(e) = index.flush().await {
+            log::warn!("Error while saving the cache index: {}", e);
+        }
+    }
+}
+
+pub fn sha256_hex(bytes: &[u8]) -> String {
+    hex::encode(Sha256::digest(bytes))
+}
+
+// PDFs in the cache folder by date, based on their dd.mm.yyyy.pdf names
+async fn cached_dates(dir: &Path) -> std::io::Result<BTreeMap<NaiveDate, PathBuf>> {
+    let mut dates = BTreeMap::new();
+    let mut entries = tokio::fs::read_dir(dir).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        let name = entry.file_name().to_string_lossy().into_owned();
+        let date = name
+            .strip_suffix(".pdf")
+            .and_then(|date| NaiveDate::parse_from_str(date, "%d.%m.%Y").ok());
+        if let Some(date) = date {
+            dates.insert(date, entry.path());
+        }
+    }
+    Ok(dates)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn revisions_and_hits() {
+        let dir = std::env::temp_dir().join(format!("zastepstwa-index-{}", std::process::id()));
+        let _ = tokio::fs::remove_dir_all(&dir).await;
+        tokio::fs::create_dir_all(&dir).await.unwrap();
+        let path = dir.join("index.json");
+        let date = NaiveDate::from_ymd_opt(2022, 10, 10).unwrap();
+        let index = Index::open(path.clone(), &dir).await.unwrap();
+
+        assert!(index.is_changed(date, b"first"));
+        assert_eq!(index.next_revision(date), 1);
+        index
+            .record_download(date, b"first", Validators::default())
+            .await
+            .unwrap();
+        // The same content again is only a check, not a new version
+        assert!(!index.is_changed(date, b"first"));
+        index
+            .record_download(date, b"first", Validators::default())
+            .await
+            .unwrap();
+        assert_eq!(index.next_revision(date), 2);
+        index
+            .record_download(date, b"second", Validators::default())
+            .await
+            .unwrap();
+        // Going back to an older content is a new version too
+        index
+            .record_download(date, b"first", Validators::default())
+            .await
+            .unwrap();
+        let entry = index.get(date).unwrap();
+        assert_eq!(entry.revisions, 3);
+        let numbers: Vec<u32> = entry
+            .history
+            .iter()
+            .map(|revision| revision.number)
+            .collect();
+        assert_eq!(numbers, [1, 2, 3]);
+        assert_eq!(entry.history[2].sha256, sha256_hex(b"first"));
+
+        // Hits are only written by flush (or the next change)
+        let saved = || async {
+            let json = tokio::fs::read(&path).await.unwrap();
+            let entries: BTreeMap<NaiveDate, Entry> = serde_json::from_slice(&json).unwrap();
+            entries[&date].hits
+        };
+        index.record_hit(date);
+        index.record_hit(date);
+        assert_eq!(saved().await, 0);
+        index.flush().await.unwrap();
+        assert_eq!(saved().await, 2);
+
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn read_doesnt_touch_the_cache() {
+        let dir = std::env::temp_dir().join(format!("zastepstwa-read-{}", std::process::id()));
+        let _ = tokio::fs::remove_dir_all(&dir).await;
+        tokio::fs::create_dir_all(&dir).await.unwrap();
+        // A PDF that open would add to the index and copy to revisions/
+        tokio::fs::write(dir.join("10.10.2022.pdf"), b"%PDF")
+            .await
+            .unwrap();
+
+        let index = Index::read(dir.join("index.json")).await.unwrap();
+        assert!(index.entries().is_empty());
+        assert!(!dir.join("index.json").exists());
+        assert!(!dir.join("revisions").exists());
+
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+    }
+}
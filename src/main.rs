@@ -3,19 +3,45 @@ use actix_web::web;
 use actix_web::{get, middleware::Logger, App, HttpServer};
 use actix_web::{HttpResponse, Responder};
 use chrono::Datelike;
-use chrono::{DateTime, Weekday};
+use chrono::Weekday;
+use chrono::{DateTime, Local};
+use clap::Parser;
 use serde::{Deserialize, Serialize};
-use serde_json::{json, Value};
-use tokio::io::AsyncWriteExt;
-
-const CACHE_TIME_MIN: i64 = 30;
-const DOMAIN: &str = "https://zastepstwa.ducky.pics";
-// Uncomment for local testing
-// const DOMAIN: &str = "http://192.168.1.253:5000";
-// const DOMAIN: &str = "http://172.20.10.3:5000";
-const MAINTENENCE: bool = true;
-const MAINTENENCE_MESSAGE: &str = "Skrót można usunąć - będzie niedługo aplikacja";
-// JSON Response Struct
+
+mod admin;
+mod api;
+mod cache;
+mod calendar;
+mod changes;
+mod cli;
+mod config;
+mod error;
+mod feed;
+mod ical;
+mod index;
+mod maintenance;
+mod parser;
+mod plan;
+mod prefetch;
+mod retention;
+mod revisions;
+mod rooms;
+mod singleflight;
+mod source;
+mod state;
+mod substitutions;
+mod summary;
+mod teachers;
+mod timetable;
+mod validate;
+
+use config::Config;
+use error::ZastepstwaError;
+use maintenance::MaintenanceState;
+use state::AppState;
+use timetable::Timetable;
+
+// JSON Response Struct, the legacy {code, link} shape that /api/v1 keeps frozen
 #[derive(Serialize)]
 struct Response {
     code: u16,
@@ -30,156 +56,65 @@ struct Date {
     year: i32,
 }
 
-async fn ready_file(day: u32, month: u32, year: i32) -> Value {
-    if MAINTENENCE {
-        return json!({"code": 500, "error": MAINTENENCE_MESSAGE});
-    }
-
-    // Check if the date is valid using chrono
-    if chrono::NaiveDate::from_ymd_opt(year, month, day).is_none() {
-        // If it isn't, return an error
-        return json!({"code": 422, "error": "Nieprawidłowa data!"});
-    }
-
-    // Check if the date is on the winter break (16.01 - 29.01)
-    if month == 1 && (16..=29).contains(&day) {
-        // If it is, return an error
-        return json!({"code": 422, "error": "Jest przerwa zimowa! Możesz odpoczywać!"});
-    }
-
-    // Make the day have 2 digits
-    let day = format!("{:02}", day);
-    // Make the month have 2 digits
-    let month = format!("{:02}", month);
-
-    // Check if the date is on the weekend
-    let date = chrono::NaiveDate::from_ymd_opt(year, month.parse().unwrap(), day.parse().unwrap());
-    if date.unwrap().weekday() == Weekday::Sat || date.unwrap().weekday() == Weekday::Sun {
-        // If it is, return an error
-        return json!({"code": 422, "error": "Wybrana data to weekend!"});
-    }
-
-    // Setup common variables
-    let date = format!("{}.{}.{}", day, month, year);
-    let filename_pdf = format!("./cached/{}.pdf", date);
-
-    // Check if the file already exists in the cache
-    if std::path::Path::new(&filename_pdf).exists() {
-        // Check if the file is younger then X minutes. If it is, return the link to the file. If it isn't, try to download the new one.
-        // If it fails, return the link to the old file. If it succeeds, return the link to the new file and delete the old one.
-        let metadata = tokio::fs::metadata(&filename_pdf)
-            .await
-            .expect("Error while getting metadata");
-        let file_age = chrono::Local::now()
-            - DateTime::from(
-                metadata
-                    .modified()
-                    .expect("Error while getting file modified date"),
-            );
-
-        // If the file is younger than X minutes, return the link to the file
-        if file_age.num_minutes() < CACHE_TIME_MIN {
-            // If it is, return the link to the file
-            return json!({
-                "code": 200,
-                "link": format!("{}/files/{}.pdf", DOMAIN, date)
-            });
+impl From<chrono::NaiveDate> for Date {
+    fn from(date: chrono::NaiveDate) -> Self {
+        Date {
+            day: date.day(),
+            month: date.month(),
+            year: date.year(),
         }
     }
+}
 
-    // If we got here, it means that the file doesn't exist or it's too old. We need to download the new one.
-    let response =
-        match reqwest::get(format!("https://zastepstwa.zschie.pl/pliki/{}.pdf", date)).await {
-            Ok(response) => response,
-            Err(_) => {
-                // If the file exists, return the link to the old file
-                if std::path::Path::new(&filename_pdf).exists() {
-                    // If it does, return the link to the file
-                    return json!({
-                        "code": 200,
-                        "link": format!("{}/files/{}.pdf", DOMAIN, date)
-                    });
-                }
-                return json!({
-                    "code": 500,
-                    "error": "Strona szkoły jest offline! Spróbuj ponownie później!"
-                });
-            }
+impl Date {
+    // "10.10.2022" or "2022-10-10", whether the date exists is checked later by ready_file
+    fn parse(text: &str) -> Option<Date> {
+        let parts: Vec<&str> = text.trim().split(['.', '-']).collect();
+        let [first, month, last] = parts[..] else {
+            return None;
+        };
+        let (day, year) = if text.contains('-') {
+            (last, first)
+        } else {
+            (first, last)
         };
+        Some(Date {
+            day: day.parse().ok()?,
+            month: month.parse().ok()?,
+            year: year.parse().ok()?,
+        })
+    }
+}
 
-    // Match different status codes from the server and act accordingly
-    match response.status().as_u16() {
-        200 => {
-            // Create the file, but first we need to make sure that the file doesn't exist
-            if std::path::Path::new(&filename_pdf).exists() {
-                // If it does, delete it
-                tokio::fs::remove_file(&filename_pdf)
-                    .await
-                    .expect("Error while deleting file");
-            }
-            let mut file = tokio::fs::File::create(&filename_pdf)
-                .await
-                .expect("Error while creating file");
-            // Download the PDF
-            let filebytes = response.bytes().await.unwrap();
-            // Write the PDF to the file
-            file.write_all(&filebytes)
-                .await
-                .expect("Error while writing file");
-            // Close the file
-            file.flush().await.expect("Error while flushing file");
-            // Return the link to the file
-            json!({
-                "code": 200,
-                "link": format!("{}/files/{}.pdf", DOMAIN, date)
-            })
-        }
-        404 => {
-            // If the server returns a 404 status code, it means that there are currently no substitutions available
-            // Check if the file exists
-            if std::path::Path::new(&filename_pdf).exists() {
-                // If it does, return the link to the file
-                json!({
-                    "code": 200,
-                    "link": format!("{}/files/{}.pdf", DOMAIN, date)
-                })
-            } else {
-                // If it doesn't, return an error
-                json!({
-                    "code": 404,
-                    "error": format!("Nie ma zastępstw na dzień {}. Spróbuj ponownie później!", date)
-                })
-            }
-        }
-        _ => {
-            // Return an error if the server returns a different status code
-            let response_status = response.status().as_u16();
-            // Check if the file exists
-            if std::path::Path::new(&filename_pdf).exists() {
-                // If it does, return the link to the file
-                json!({
-                    "code": 200,
-                    "link": format!("{}/files/{}.pdf", DOMAIN, date)
-                })
-            } else {
-                // If it doesn't, return an error
-                json!({
-                    "code": 404,
-                    "error": format!("Server zwrócił nieznany status {}. Spróbuj ponownie później!", response_status)
-                })
-            }
-        }
+// The date picked with ?date=10.10.2022 or ?when=today|tomorrow, exactly one of them has to be given
+fn requested_date(date: Option<&str>, when: Option<&str>) -> Result<Date, ZastepstwaError> {
+    match (date, when) {
+        (Some(date), None) => Date::parse(date).ok_or_else(|| {
+            ZastepstwaError::InvalidParameter("Nieprawidłowa wartość parametru 'date'".to_string())
+        }),
+        (None, Some(when)) => resolve_when(when),
+        _ => Err(ZastepstwaError::InvalidParameter(
+            "Podaj parametr 'date' albo 'when'".to_string(),
+        )),
     }
 }
 
+// Error PDF for the routes that always have to return a PDF
+async fn missing_pdf() -> Result<NamedFile, ZastepstwaError> {
+    NamedFile::open_async("./pdf/brak.pdf").await.map_err(|e| {
+        log::error!("Error while opening pdf/brak.pdf: {}", e);
+        ZastepstwaError::Storage
+    })
+}
+
 // /?day=1&month=1&year=2021
 #[get("/")]
-async fn get_data(date: web::Query<Date>) -> impl Responder {
-    let (day, month, year) = (date.day, date.month, date.year);
-    let json = ready_file(day, month, year).await;
-    HttpResponse::Ok()
-        .content_type("application/json")
-        .body(json.to_string())
+async fn get_data(
+    state: web::Data<AppState>,
+    date: web::Query<Date>,
+) -> Result<HttpResponse, ZastepstwaError> {
+    let link = state.ready_file(&date).await?.link;
+    Ok(HttpResponse::Ok().json(Response { code: 200, link }))
 }
 
 #[derive(Deserialize)]
@@ -190,132 +125,175 @@ struct When {
 // /auto/?when=today
 // /auto/?when=tomorrow
 #[get("/auto/")]
-async fn auto_get_data(when: web::Query<When>) -> impl Responder {
-    let when = when.when.to_lowercase();
+async fn auto_get_data(
+    state: web::Data<AppState>,
+    when: web::Query<When>,
+) -> Result<HttpResponse, ZastepstwaError> {
+    let link = auto_link(&state, &when.when).await?;
+    Ok(HttpResponse::Ok().json(Response { code: 200, link }))
+}
+
+// The link for when=today|tomorrow, shared with /api/v1/auto/
+async fn auto_link(state: &AppState, when: &str) -> Result<String, ZastepstwaError> {
+    let when = when.to_lowercase();
     if when != "today" && when != "tomorrow" {
-        let json = json!({"code": 422, "error": "Nieprawidłowa wartość parametru 'when'"});
-        return HttpResponse::UnprocessableEntity()
-            .content_type("application/json")
-            .body(json.to_string());
+        return Err(invalid_when());
     }
-    if MAINTENENCE {
-        let json = json!({"code": 500, "error": MAINTENENCE_MESSAGE});
-        return HttpResponse::InternalServerError()
-            .content_type("application/json")
-            .body(json.to_string());
+    if let Some(message) = state.maintenance.active_message() {
+        return Err(ZastepstwaError::Maintenance(message));
     }
+    let date = resolve_when(&when)?;
+
+    Ok(state.ready_file(&date).await?.link)
+}
+
+// The date for when=today|tomorrow, a weekend gets its own message
+fn resolve_when(when: &str) -> Result<Date, ZastepstwaError> {
     // Get current date
-    let (day, month, year): (u32, u32, i32) = match when.as_str() {
+    let date: Date = match when.to_lowercase().as_str() {
         "today" => match chrono::Local::now().weekday() {
             Weekday::Sat => {
-                let json =
-                    json!({"code": 422, "error": "Jest dziś sobota, nie ma dziś żadnych lekcji!"});
-                return HttpResponse::UnprocessableEntity()
-                    .content_type("application/json")
-                    .body(json.to_string());
+                return Err(ZastepstwaError::Weekend(
+                    "Jest dziś sobota, nie ma dziś żadnych lekcji!",
+                ));
             }
             Weekday::Sun => {
-                let json = json!({"code": 422, "error": "Jest dziś niedziela, nie ma dziś żadnych lekcji!"});
-                return HttpResponse::UnprocessableEntity()
-                    .content_type("application/json")
-                    .body(json.to_string());
-            }
-            _ => {
-                let date = chrono::Local::now().naive_local().date();
-                (date.day(), date.month(), date.year())
+                return Err(ZastepstwaError::Weekend(
+                    "Jest dziś niedziela, nie ma dziś żadnych lekcji!",
+                ));
             }
+            _ => Date::from(chrono::Local::now().naive_local().date()),
         },
         "tomorrow" => match chrono::Local::now().weekday() {
             Weekday::Fri => {
-                let json =
-                    json!({"code": 422, "error": "Jutro jest sobota, więc nie ma zastępstw!"});
-                return HttpResponse::UnprocessableEntity()
-                    .content_type("application/json")
-                    .body(json.to_string());
+                return Err(ZastepstwaError::Weekend(
+                    "Jutro jest sobota, więc nie ma zastępstw!",
+                ));
             }
             Weekday::Sat => {
-                let json =
-                    json!({"code": 422, "error": "Jutro jest niedziela, więc nie ma zastępstw!"});
-                return HttpResponse::UnprocessableEntity()
-                    .content_type("application/json")
-                    .body(json.to_string());
-            }
-            _ => {
-                let date = chrono::Local::now().naive_local().date() + chrono::Duration::days(1);
-                (date.day(), date.month(), date.year())
+                return Err(ZastepstwaError::Weekend(
+                    "Jutro jest niedziela, więc nie ma zastępstw!",
+                ));
             }
+            _ => Date::from(chrono::Local::now().naive_local().date() + chrono::Duration::days(1)),
         },
-        _ => {
-            let json = json!({"code": 422, "error": "Nieprawidłowa wartość parametru 'when'"});
-            return HttpResponse::UnprocessableEntity()
-                .content_type("application/json")
-                .body(json.to_string());
-        }
+        _ => return Err(invalid_when()),
     };
+    Ok(date)
+}
 
-    let json = ready_file(day, month, year).await;
-
-    HttpResponse::Ok()
-        .content_type("application/json")
-        .body(json.to_string())
+fn invalid_when() -> ZastepstwaError {
+    ZastepstwaError::InvalidParameter("Nieprawidłowa wartość parametru 'when'".to_string())
 }
 
 // File serving (for example, localhost:5000/files/10.10.2022.pdf)
 #[get("/files/{day}.{month}.{year}.pdf")]
-async fn files(file: web::Path<Date>) -> NamedFile {
+async fn files(
+    state: web::Data<AppState>,
+    file: web::Path<Date>,
+) -> Result<NamedFile, ZastepstwaError> {
     let file = file.into_inner();
-    let file = format!("{}.{}.{}.pdf", file.day, file.month, file.year);
+    // Cached files always use 2 digit days and months
+    let file = state
+        .config
+        .cached_pdf(&format!("{:02}.{:02}.{}", file.day, file.month, file.year));
     // Check if the file exists
-    if !std::path::Path::new(&format!("./cached/{}", file)).exists() {
+    if !file.exists() {
         // If it doesn't, return an error
-        return NamedFile::open_async("./pdf/brak.pdf")
-            .await
-            .expect("Error while opening file");
+        return missing_pdf().await;
+    }
+
+    match NamedFile::open_async(&file).await {
+        Ok(file) => Ok(file),
+        Err(e) => {
+            log::error!("Error while opening {}: {}", file.display(), e);
+            missing_pdf().await
+        }
     }
+}
+
+#[derive(Serialize)]
+struct StatusResponse {
+    status: &'static str,
+    maintenance: MaintenanceStatus,
+}
 
-    NamedFile::open_async(format!("./cached/{}", file))
-        .await
-        .expect("Error while opening file")
+#[derive(Serialize)]
+struct MaintenanceStatus {
+    // Enabled and not past "until"
+    active: bool,
+    enabled: bool,
+    message: String,
+    until: Option<DateTime<Local>>,
 }
 
 // Status page
 #[get("/status")]
-async fn status() -> impl Responder {
-    HttpResponse::Ok().body("OK")
+async fn status(state: web::Data<AppState>) -> impl Responder {
+    let maintenance = state.maintenance.get();
+    HttpResponse::Ok().json(StatusResponse {
+        status: "OK",
+        maintenance: MaintenanceStatus {
+            active: maintenance.is_active(),
+            enabled: maintenance.enabled,
+            message: maintenance.message,
+            until: maintenance.until,
+        },
+    })
+}
+
+#[derive(Serialize)]
+struct StatsResponse {
+    files: usize,
+    size: u64,
+    revisions: u64,
+    hits: u64,
+    // Downloads that didn't look like a PDF since the server started
+    rejected: u64,
+    last_checked: Option<DateTime<Local>>,
+    last_changed: Option<DateTime<Local>>,
 }
 
 // Statistics page
 #[get("/stats")]
-async fn stats() -> impl Responder {
-    // Get the number of files in the cached folder
-    let filecount = std::fs::read_dir("./cached").unwrap().count();
-    // Return the number of files
-    let json = json!({ "files": filecount });
-    HttpResponse::Ok().json(json)
+async fn stats(state: web::Data<AppState>) -> impl Responder {
+    let entries = state.index.entries();
+    // Return the number of files and totals from the cache index
+    HttpResponse::Ok().json(StatsResponse {
+        files: entries.len(),
+        size: entries.iter().map(|(_, entry)| entry.size).sum(),
+        revisions: entries
+            .iter()
+            .map(|(_, entry)| entry.revisions as u64)
+            .sum(),
+        hits: entries.iter().map(|(_, entry)| entry.hits).sum(),
+        rejected: validate::rejected(),
+        last_checked: entries.iter().map(|(_, entry)| entry.last_checked).max(),
+        last_changed: entries.iter().map(|(_, entry)| entry.last_changed).max(),
+    })
 }
 
 // getpdf route for making this work as fast as possible using one request only. if it fails just return an error pdf located in the pdf/brak.pdf directory. used for the android app
 #[get("/getpdf")]
-async fn getpdf(date: web::Query<Date>) -> NamedFile {
+async fn getpdf(
+    state: web::Data<AppState>,
+    date: web::Query<Date>,
+) -> Result<NamedFile, ZastepstwaError> {
     // This strictly returns a PDF file, not JSON
-    let (day, month, year) = (date.day, date.month, date.year);
-    let res = ready_file(day, month, year).await;
+    let res = state.ready_file(&date).await;
     // Check if the request was successful
-    if res["code"] == 200 {
+    if let Ok(ready) = res {
         // If it was, return the file, get it from the cached folder using the date
-        let date = format!("{:02}.{:02}.{}", day, month, year);
-        let file = match NamedFile::open_async(format!("./cached/{}.pdf", date)).await {
-            Ok(file) => file,
-            Err(_) => {
-                return NamedFile::open_async("./pdf/brak.pdf")
-                    .await
-                    .expect("Error while opening file");
+        match NamedFile::open_async(state.config.cached_pdf(&ready.name)).await {
+            Ok(file) => Ok(file),
+            Err(e) => {
+                log::error!("Error while opening {}: {}", ready.name, e);
+                missing_pdf().await
             }
-        };
-        file
+        }
     } else {
         // If it wasn't, return the error pdf
-        NamedFile::open_async("./pdf/brak.pdf").await.unwrap()
+        missing_pdf().await
     }
 }
 
@@ -329,11 +307,99 @@ async fn main() -> std::io::Result<()> {
 
     env_logger::init(); // Set up logging
 
-    tokio::fs::create_dir_all("./cached").await.unwrap(); // Set up the cache folder if it doesn't exist
+    let cli = cli::Cli::parse();
+
+    // Load the config file and environment overrides, refuse to start if something is wrong
+    let config = match Config::load() {
+        Ok(config) => config,
+        Err(e) => {
+            log::error!("{}", e);
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, e));
+        }
+    };
+
+    tokio::fs::create_dir_all(&config.cache.dir).await?; // Set up the cache folder if it doesn't exist
+
+    match cli.command.unwrap_or(cli::Command::Serve) {
+        cli::Command::Serve => serve(config).await,
+        command => {
+            let code = cli::run(config, command).await?;
+            std::process::exit(code);
+        }
+    }
+}
+
+async fn serve(config: Config) -> std::io::Result<()> {
+    let bind = (config.server.host.clone(), config.server.port);
+
+    // Only one process may change the cache at a time, the lock is held until the server stops
+    let Some(_lock) = cache::lock(&config.cache.dir)? else {
+        let message = format!(
+            "{} is used by another server or a cache command",
+            config.cache.dir.display()
+        );
+        log::error!("{}", message);
+        return Err(std::io::Error::other(message));
+    };
+    // Nobody else can be writing now, so every temp file is left over from a crash
+    cache::remove_temp_files(&config.cache.dir).await?;
+
+    // Maintenance state saved by the admin API takes priority over the config file
+    let maintenance = MaintenanceState::load(&config)?;
+    // The normal plan is optional, views that need it say so if it's missing
+    let timetable = match &config.school.timetable {
+        Some(path) => match Timetable::load(path) {
+            Ok(timetable) => {
+                log::info!(
+                    "Imported {} lessons from {}",
+                    timetable.lessons.len(),
+                    path.display()
+                );
+                timetable
+            }
+            Err(e) => {
+                log::error!("{}", e);
+                return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, e));
+            }
+        },
+        None => Timetable::default(),
+    };
+    let timetable = web::Data::new(timetable);
+    let state = web::Data::new(AppState::open(config, maintenance).await?);
+
+    // Remove old files from the cache every few hours
+    if state.config.retention.is_enabled() {
+        actix_web::rt::spawn(retention::janitor(state.clone()));
+    }
+
+    // Hits aren't saved on every request
+    actix_web::rt::spawn({
+        let state = state.clone();
+        async move { index::flush_hits(&state.index).await }
+    });
+
+    // Keep today and the next school day fresh in the background
+    if state.config.prefetch.enabled {
+        actix_web::rt::spawn(prefetch::run(state.clone()));
+    }
 
     // Start the server
-    HttpServer::new(|| {
+    let shared = state.clone();
+    HttpServer::new(move || {
         App::new()
+            // Return bad query parameters in the same JSON format as every other error
+            .app_data(
+                web::QueryConfig::default().error_handler(|err, _| {
+                    ZastepstwaError::InvalidParameter(err.to_string()).into()
+                }),
+            )
+            .app_data(
+                web::PathConfig::default().error_handler(|err, _| {
+                    ZastepstwaError::InvalidParameter(err.to_string()).into()
+                }),
+            )
+            .app_data(shared.clone())
+            .app_data(timetable.clone())
             .wrap(Logger::default())
             .service(get_data)
             .service(auto_get_data)
@@ -341,8 +407,29 @@ async fn main() -> std::io::Result<()> {
             .service(status)
             .service(stats)
             .service(getpdf)
+            .service(revisions::list_revisions)
+            .service(revisions::revision_file)
+            .service(substitutions::get_substitutions)
+            .service(substitutions::class_substitutions)
+            .service(changes::substitution_changes)
+            .service(teachers::teacher_substitutions)
+            .service(rooms::room_occupancy)
+            .service(timetable::class_timetable)
+            .service(summary::class_summary)
+            .service(plan::plan_page)
+            .service(ical::class_feed)
+            .service(ical::teacher_feed)
+            .service(feed::atom_feed)
+            .service(feed::class_feed)
+            .service(api::v1())
+            .service(api::v2())
+            .service(admin::enable_maintenance)
+            .service(admin::disable_maintenance)
     })
-    .bind(("0.0.0.0", 5000))?
+    .bind(bind)?
     .run()
-    .await
+    .await?;
+
+    // Don't lose the hits counted since the last save
+    state.index.flush().await
 }
@@ -0,0 +1,93 @@
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::sync::RwLock;
+
+use crate::cache;
+use crate::config::Config;
+
+// Maintenance mode as it's stored on disk and shown in /status
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Maintenance {
+    pub enabled: bool,
+    pub message: String,
+    // Maintenance turns itself off after this time (if set)
+    pub until: Option<DateTime<Local>>,
+}
+
+impl Maintenance {
+    // Maintenance is active if it's enabled and the scheduled end time (if any) hasn't passed yet
+    pub fn is_active(&self) -> bool {
+        self.enabled && self.until.is_none_or(|until| Local::now() < until)
+    }
+}
+
+// Shared maintenance state that can be changed at runtime through the admin API
+pub struct MaintenanceState {
+    current: RwLock<Maintenance>,
+    path: PathBuf,
+}
+
+impl MaintenanceState {
+    // Load the saved state if there is one, otherwise start with the values from the config file
+    pub fn load(config: &Config) -> std::io::Result<MaintenanceState> {
+        let path = config.maintenance.state_file.clone();
+        let current = match std::fs::read_to_string(&path) {
+            Ok(contents) => serde_json::from_str(&contents).map_err(|e| {
+                std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    format!("invalid maintenance state file {}: {}", path.display(), e),
+                )
+            })?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Maintenance {
+                enabled: config.maintenance.enabled,
+                message: config.maintenance.message.clone(),
+                until: None,
+            },
+            Err(e) => return Err(e),
+        };
+        Ok(MaintenanceState {
+            current: RwLock::new(current),
+            path,
+        })
+    }
+
+    // Maintenance switched off, whatever was saved. Used by the command line tools.
+    pub fn inactive(config: &Config) -> MaintenanceState {
+        MaintenanceState {
+            current: RwLock::new(Maintenance {
+                enabled: false,
+                message: config.maintenance.message.clone(),
+                until: None,
+            }),
+            path: config.maintenance.state_file.clone(),
+        }
+    }
+
+    pub fn get(&self) -> Maintenance {
+        self.current.read().unwrap().clone()
+    }
+
+    // Returns the message to show if maintenance is currently active
+    pub fn active_message(&self) -> Option<String> {
+        let current = self.current.read().unwrap();
+        if current.is_active() {
+            Some(current.message.clone())
+        } else {
+            None
+        }
+    }
+
+    // Replace the state and save it so it survives a restart
+    pub async fn set(&self, maintenance: Maintenance) -> std::io::Result<()> {
+        save(&self.path, &maintenance).await?;
+        *self.current.write().unwrap() = maintenance;
+        Ok(())
+    }
+}
+
+// Written like the cached PDFs, so a crash in the middle can't leave a broken file that stops the next start
+async fn save(path: &Path, maintenance: &Maintenance) -> std::io::Result<()> {
+    let json = serde_json::to_vec_pretty(maintenance)?;
+    cache::store(path, &json).await
+}
@@ -0,0 +1,519 @@
+use pdf_extract::{MediaBox, OutputDev, OutputError, Transform};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+// What happens with a lesson
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Kind {
+    // Another teacher takes the lesson
+    Substituted,
+    // The lesson doesn't take place
+    Cancelled,
+    // The class joins another class or group
+    Joined,
+    // The lesson takes place somewhere (or sometime) else
+    Moved,
+}
+
+// One row of the substitution table, rows for several classes are split into one record per class
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Substitution {
+    pub lesson: u32,
+    pub class: String,
+    pub group: Option<String>,
+    pub absent_teacher: String,
+    pub substitute_teacher: Option<String>,
+    pub subject: Option<String>,
+    pub room: Option<String>,
+    pub note: Option<String>,
+    pub kind: Kind,
+}
+
+impl Substitution {
+    // Is this record for the class? Without a group every row of the class counts,
+    // with one only rows for the whole class and for that group do.
+    pub fn is_for(&self, class: &str, group: Option<&str>) -> bool {
+        if normalize(&self.class) != normalize(class) {
+            return false;
+        }
+        match (&self.group, group) {
+            (Some(own), Some(group)) => normalize(own) == normalize(group),
+            _ => true,
+        }
+    }
+
+    // The room a moved lesson takes place in, from notes like "przeniesiona do s. 14"
+    pub fn moved_to(&self) -> Option<String> {
+        if self.kind != Kind::Moved {
+            return None;
+        }
+        let note = self.note.as_deref()?;
+        let start = note.find("s.")? + 2;
+        let room: String = note[start..]
+            .trim_start()
+            .chars()
+            .take_while(|c| !c.is_whitespace() && *c != ',' && *c != ')')
+            .collect();
+        (!room.is_empty()).then_some(room)
+    }
+}
+
+// "3 ti" and "3TI" are the same class
+pub fn normalize(text: &str) -> String {
+    text.chars()
+        .filter(|c| !c.is_whitespace())
+        .flat_map(char::to_uppercase)
+        .collect()
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParseError {
+    // The file couldn't be read as a PDF
+    Pdf(String),
+    // The PDF has no substitution table (for example a "nothing published yet" page)
+    NoTable,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseError::Pdf(reason) => write!(f, "couldn't read the PDF: {}", reason),
+            ParseError::NoTable => write!(f, "no substitution table found"),
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+// Pull the substitution table out of a PDF printed by Plan lekcji Optivum.
+// The text is placed on the page with absolute positions, so the table is rebuilt from where every piece of text is:
+// pieces on the same height form a row and the header row ("lekcja | opis | zastępca | uwagi") tells which column is where.
+pub fn parse(bytes: &[u8]) -> Result<Vec<Substitution>, ParseError> {
+    let fragments = extract(bytes)?;
+    let rows = table_rows(rows(fragments))?;
+    Ok(rows.into_iter().flat_map(records).collect())
+}
+
+// A piece of text that was printed in one go
+#[derive(Debug)]
+struct Fragment {
+    page: u32,
+    x: f64,
+    y: f64,
+    // Font size, used as the unit for every distance
+    size: f64,
+    // Where the last character ends
+    end: f64,
+    text: String,
+}
+
+// Collects the text of a PDF as fragments with their position
+#[derive(Default)]
+struct Collector {
+    page: u32,
+    fragments: Vec<Fragment>,
+}
+
+impl OutputDev for Collector {
+    fn begin_page(
+        &mut self,
+        page_num: u32,
+        _media_box: &MediaBox,
+        _art_box: Option<(f64, f64, f64, f64)>,
+    ) -> Result<(), OutputError> {
+        self.page = page_num;
+        Ok(())
+    }
+
+    fn end_page(&mut self) -> Result<(), OutputError> {
+        Ok(())
+    }
+
+    fn output_character(
+        &mut self,
+        trm: &Transform,
+        width: f64,
+        _spacing: f64,
+        font_size: f64,
+        char: &str,
+    ) -> Result<(), OutputError> {
+        let (x, y) = (trm.m31, trm.m32);
+        let size = (trm.m11 * trm.m22).abs().sqrt() * font_size;
+        let end = x + width * size;
+
+        // Characters right after each other on the same line belong to the same fragment,
+        // a gap wider than a character starts the next cell of the table
+        if let Some(last) = self.fragments.last_mut() {
+            if last.page == self.page
+                && (y - last.y).abs() < size * 0.5
+                && x > last.end - size * 0.5
+                && x - last.end < size
+            {
+                if x - last.end > size * 0.1 && !last.text.ends_with(' ') {
+                    last.text.push(' ');
+                }
+                last.text.push_str(char);
+                last.end = end;
+                return Ok(());
+            }
+        }
+        self.fragments.push(Fragment {
+            page: self.page,
+            x,
+            y,
+            size,
+            end,
+            text: char.to_string(),
+        });
+        Ok(())
+    }
+
+    fn begin_word(&mut self) -> Result<(), OutputError> {
+        Ok(())
+    }
+
+    fn end_word(&mut self) -> Result<(), OutputError> {
+        Ok(())
+    }
+
+    fn end_line(&mut self) -> Result<(), OutputError> {
+        Ok(())
+    }
+}
+
+fn extract(bytes: &[u8]) -> Result<Vec<Fragment>, ParseError> {
+    // pdf-extract panics on some broken files instead of returning an error
+    std::panic::catch_unwind(|| {
+        let doc =
+            pdf_extract::Document::load_mem(bytes).map_err(|e| ParseError::Pdf(e.to_string()))?;
+        let mut collector = Collector::default();
+        pdf_extract::output_doc(&doc, &mut collector)
+            .map_err(|e| ParseError::Pdf(format!("{:?}", e)))?;
+        Ok(collector.fragments)
+    })
+    .unwrap_or_else(|_| Err(ParseError::Pdf("the PDF is malformed".to_string())))
+}
+
+// Group the fragments into lines, top to bottom and left to right
+fn rows(mut fragments: Vec<Fragment>) -> Vec<Vec<Fragment>> {
+    fragments.retain(|fragment| !fragment.text.trim().is_empty());
+    // PDF coordinates grow upwards, so the top of the page has the biggest y
+    fragments.sort_by(|a, b| a.page.cmp(&b.page).then(b.y.total_cmp(&a.y)));
+
+    let mut rows: Vec<Vec<Fragment>> = Vec::new();
+    for fragment in fragments {
+        match rows.last_mut() {
+            Some(row)
+                if row[0].page == fragment.page
+                    && (row[0].y - fragment.y).abs() < row[0].size.min(fragment.size) * 0.5 =>
+            {
+                row.push(fragment)
+            }
+            _ => rows.push(vec![fragment]),
+        }
+    }
+    for row in rows.iter_mut() {
+        row.sort_by(|a, b| a.x.total_cmp(&b.x));
+    }
+    rows
+}
+
+// A table row with the text of every column, before it's split into records
+#[derive(Debug, Default)]
+struct Row {
+    teacher: String,
+    lesson: u32,
+    description: String,
+    substitute: String,
+    note: String,
+}
+
+// Where the columns of the table start
+struct Columns {
+    description: f64,
+    substitute: f64,
+    note: f64,
+}
+
+impl Columns {
+    // The header row is the one with "lekcja" and "opis" in it
+    fn from_header(row: &[Fragment]) -> Option<Columns> {
+        let find = |name: &str| {
+            row.iter()
+                .find(|fragment| fragment.text.trim().to_lowercase() == name)
+                .map(|fragment| fragment.x)
+        };
+        find("lekcja")?;
+        Some(Columns {
+            description: find("opis")?,
+            substitute: find("zastępca")?,
+            note: find("uwagi")?,
+        })
+    }
+
+    // 0 = lesson, 1 = description, 2 = substitute, 3 = note
+    fn of(&self, fragment: &Fragment) -> usize {
+        // Text is sometimes printed a little to the left of the column header
+        let x = fragment.x + fragment.size;
+        if x >= self.note {
+            3
+        } else if x >= self.substitute {
+            2
+        } else if x >= self.description {
+            1
+        } else {
+            0
+        }
+    }
+}
+
+// Walk through the lines and put together the table rows. Every absent teacher has their name printed
+// above their part of the table, so the last name we saw is the teacher of the rows that follow.
+fn table_rows(lines: Vec<Vec<Fragment>>) -> Result<Vec<Row>, ParseError> {
+    let mut columns: Option<Columns> = None;
+    let mut teacher = String::new();
+    let mut rows: Vec<Row> = Vec::new();
+
+    for line in lines {
+        if let Some(header) = Columns::from_header(&line) {
+            columns = Some(header);
+            continue;
+        }
+        let text = join(line.iter().map(|fragment| fragment.text.as_str()));
+        // The title and the footer of the printout
+        if text.starts_with("Zastępstwa") || text.contains("Optivum") {
+            continue;
+        }
+
+        let Some(columns) = &columns else {
+            // Before the first header only the teacher's name is interesting
+            teacher = teacher_name(&text);
+            continue;
+        };
+        let mut cells: [Vec<&str>; 4] = Default::default();
+        for fragment in &line {
+            cells[columns.of(fragment)].push(fragment.text.as_str());
+        }
+        let [lesson, description, substitute, note] = cells.map(|cell| join(cell.into_iter()));
+
+        if let Some(number) = lesson_number(&lesson) {
+            rows.push(Row {
+                teacher: teacher.clone(),
+                lesson: number,
+                description,
+                substitute,
+                note,
+            });
+        } else if lesson.is_empty() {
+            // Long text wraps to the next line, which has no lesson number
+            if let Some(row) = rows.last_mut() {
+                append(&mut row.description, &description);
+                append(&mut row.substitute, &substitute);
+                append(&mut row.note, &note);
+            }
+        } else {
+            teacher = teacher_name(&text);
+        }
+    }
+
+    if columns.is_none() {
+        return Err(ParseError::NoTable);
+    }
+    Ok(rows)
+}
+
+fn join<'a>(parts: impl Iterator<Item = &'a str>) -> String {
+    parts
+        .map(str::trim)
+        .filter(|part| !part.is_empty())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn append(cell: &mut String, text: &str) {
+    if text.is_empty() {
+        return;
+    }
+    if !cell.is_empty() {
+        cell.push(' ');
+    }
+    cell.push_str(text);
+}
+
+// "1" or "1."
+fn lesson_number(text: &str) -> Option<u32> {
+    text.trim_end_matches('.').parse().ok()
+}
+
+// "Jan Kowalski (nieobecny)" -> "Jan Kowalski"
+fn teacher_name(text: &str) -> String {
+    match text.find(" (") {
+        Some(start) if text.ends_with(')') => text[..start].trim().to_string(),
+        _ => text.trim().to_string(),
+    }
+}
+
+// Split a table row into one record per class
+fn records(row: Row) -> Vec<Substitution> {
+    let (classes, rest) = match row.description.split_once(" - ") {
+        Some((classes, rest)) => (classes, Some(rest)),
+        None => (row.description.as_str(), None),
+    };
+    let (subject, room) = match rest {
+        Some(rest) => split_room(rest),
+        None => (None, None),
+    };
+
+    let substitute = non_empty(&row.substitute);
+    let note = non_empty(&row.note);
+    let kind = kind(substitute.as_deref(), note.as_deref());
+    // Instead of a teacher, the substitute column sometimes says what happens with the class
+    let (substitute, note) = match substitute {
+        Some(text) if is_cancellation(&text) => (None, note.or(Some(text))),
+        substitute => (substitute, note),
+    };
+
+    classes
+        .split(',')
+        .map(str::trim)
+        .filter(|class| !class.is_empty())
+        .map(|class| {
+            let (class, group) = split_group(class);
+            Substitution {
+                lesson: row.lesson,
+                class,
+                group,
+                absent_teacher: row.teacher.clone(),
+                substitute_teacher: substitute.clone(),
+                subject: subject.clone(),
+                room: room.clone(),
+                note: note.clone(),
+                kind,
+            }
+        })
+        .collect()
+}
+
+fn non_empty(text: &str) -> Option<String> {
+    let text = text.trim();
+    (!text.is_empty()).then(|| text.to_string())
+}
+
+// "matematyka, s. 12" or "matematyka (s. 12)"
+fn split_room(text: &str) -> (Option<String>, Option<String>) {
+    let text = text.trim();
+    if let Some((subject, room)) = text.rsplit_once(", s.") {
+        return (non_empty(subject), non_empty(room));
+    }
+    if let Some(inner) = text.strip_suffix(')') {
+        if let Some((subject, room)) = inner.rsplit_once("(s.") {
+            return (non_empty(subject), non_empty(room));
+        }
+    }
+    (non_empty(text), None)
+}
+
+// "3TI gr.1", "3TI gr. 1", "3TI|1" and "3TI (1/2)" are all group 1 of 3TI
+fn split_group(class: &str) -> (String, Option<String>) {
+    if let Some((class, group)) = class.split_once('|') {
+        return (class.trim().to_string(), non_empty(group));
+    }
+    if let Some(start) = class.find("gr.") {
+        return (
+            class[..start].trim().to_string(),
+            non_empty(&class[start + 3..]),
+        );
+    }
+    if let Some(inner) = class.strip_suffix(')') {
+        if let Some((class, group)) = inner.split_once('(') {
+            if let Some((group, _)) = group.split_once('/') {
+                return (class.trim().to_string(), non_empty(group));
+            }
+        }
+    }
+    (class.trim().to_string(), None)
+}
+
+fn is_cancellation(text: &str) -> bool {
+    let text = text.to_lowercase();
+    ["odwoł", "zwolnien", "przychodzą później", "nie ma lekcji"]
+        .iter()
+        .any(|word| text.contains(word))
+}
+
+fn kind(substitute: Option<&str>, note: Option<&str>) -> Kind {
+    let text = format!("{} {}", substitute.unwrap_or(""), note.unwrap_or("")).to_lowercase();
+    if is_cancellation(&text) {
+        Kind::Cancelled
+    } else if text.contains("łączon") {
+        Kind::Joined
+    } else if text.contains("przeniesion") {
+        Kind::Moved
+    } else if substitute.is_some() {
+        Kind::Substituted
+    } else {
+        // Nobody takes the lesson
+        Kind::Cancelled
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Compare the parser output with the expected JSON next to the sample PDF
+    fn golden(pdf: &[u8], expected: &str) {
+        let expected: Vec<Substitution> = serde_json::from_str(expected).unwrap();
+        assert_eq!(parse(pdf).unwrap(), expected);
+    }
+
+    #[test]
+    fn basic() {
+        golden(
+            include_bytes!("../tests/fixtures/basic.pdf"),
+            include_str!("../tests/fixtures/basic.json"),
+        );
+    }
+
+    #[test]
+    fn multipage() {
+        golden(
+            include_bytes!("../tests/fixtures/multipage.pdf"),
+            include_str!("../tests/fixtures/multipage.json"),
+        );
+    }
+
+    #[test]
+    fn class_and_group_filter() {
+        let records = parse(include_bytes!("../tests/fixtures/basic.pdf")).unwrap();
+        let lessons = |class, group| {
+            records
+                .iter()
+                .filter(|record| record.is_for(class, group))
+                .map(|record| record.lesson)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(lessons("3TI", None), [1, 2]);
+        assert_eq!(lessons("3ti", Some("1")), [1, 2]);
+        assert_eq!(lessons("3TI", Some("2")), [1]);
+        assert_eq!(lessons("1c", None), [4]);
+        assert_eq!(lessons("4TE", Some("1")), [6]);
+    }
+
+    #[test]
+    fn not_published_page() {
+        assert_eq!(
+            parse(include_bytes!("../pdf/brak.pdf")),
+            Err(ParseError::NoTable)
+        );
+    }
+
+    #[test]
+    fn not_a_pdf() {
+        assert!(matches!(
+            parse(b"<html>Captive portal</html>"),
+            Err(ParseError::Pdf(_))
+        ));
+    }
+}
@@ -0,0 +1,311 @@
+use actix_web::http::StatusCode;
+use actix_web::{get, web, HttpResponse, ResponseError};
+use chrono::{Duration, NaiveDate};
+use serde::Deserialize;
+
+use crate::calendar;
+use crate::config::Holiday;
+use crate::error::ZastepstwaError;
+use crate::parser::{Kind, Substitution};
+use crate::state::AppState;
+use crate::{resolve_when, Date};
+
+// Text is put into the page by hand, so everything from the PDF and the query has to be escaped
+pub fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+// Query parameters can't be escaped like HTML, only letters, digits and a few safe characters are kept as they are
+fn encode(text: &str) -> String {
+    let mut encoded = String::new();
+    for byte in text.bytes() {
+        if byte.is_ascii_alphanumeric() || b"-_.~".contains(&byte) {
+            encoded.push(byte as char);
+        } else {
+            encoded.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    encoded
+}
+
+fn kind_name(kind: Kind) -> &'static str {
+    match kind {
+        Kind::Substituted => "zastępstwo",
+        Kind::Cancelled => "odwołana",
+        Kind::Joined => "łączona",
+        Kind::Moved => "przeniesiona",
+    }
+}
+
+// The class and group picked in the query, kept in every link on the page
+struct Filter<'a> {
+    class: Option<&'a str>,
+    group: Option<&'a str>,
+}
+
+impl Filter<'_> {
+    fn query(&self) -> String {
+        let mut query = Vec::new();
+        if let Some(class) = self.class {
+            query.push(format!("class={}", encode(class)));
+        }
+        if let Some(group) = self.group {
+            query.push(format!("group={}", encode(group)));
+        }
+        if query.is_empty() {
+            String::new()
+        } else {
+            format!("?{}", query.join("&"))
+        }
+    }
+}
+
+// The whole page, the same frame for the table, the PDF fallback and errors
+fn page(holidays: &[Holiday], date: NaiveDate, filter: &Filter, body: &str) -> String {
+    let title = format!("Zastępstwa na {}", date.format("%d.%m.%Y"));
+    let previous = calendar::previous_school_day(holidays, date);
+    let next = calendar::next_school_day(holidays, date);
+    format!(
+        r#"<!DOCTYPE html>
+<html lang="pl">
+<head>
+<meta charset="utf-8">
+<meta name="viewport" content="width=device-width, initial-scale=1">
+<title>{title}</title>
+<style>
+body {{ font-family: sans-serif; margin: 0 auto; max-width: 48rem; padding: 0.5rem; }}
+nav ul {{ display: flex; justify-content: space-between; list-style: none; padding: 0; }}
+table {{ border-collapse: collapse; width: 100%; }}
+th, td {{ border-bottom: 1px solid #ccc; padding: 0.4rem; text-align: left; vertical-align: top; }}
+.cancelled {{ text-decoration: line-through; }}
+</style>
+</head>
+<body>
+<header>
+<h1>{title}</h1>
+<nav aria-label="Wybór dnia">
+<ul>
+<li><a rel="prev" href="/plan/{previous_link}{query}">&larr; {previous}</a></li>
+<li><a href="/plan/today{query}">Dzisiaj</a></li>
+<li><a rel="next" href="/plan/{next_link}{query}">{next} &rarr;</a></li>
+</ul>
+</nav>
+<form method="get" action="/plan/{date_link}">
+<label for="class">Klasa</label>
+<input id="class" name="class" value="{class}" size="6">
+<button type="submit">Pokaż</button>
+</form>
+</header>
+<main>
+{body}
+</main>
+</body>
+</html>
+"#,
+        title = escape(&title),
+        previous = previous.format("%d.%m"),
+        next = next.format("%d.%m"),
+        previous_link = previous.format("%d.%m.%Y"),
+        next_link = next.format("%d.%m.%Y"),
+        date_link = date.format("%d.%m.%Y"),
+        query = escape(&filter.query()),
+        class = escape(filter.class.unwrap_or_default()),
+    )
+}
+
+// The parsed substitutions as a table, sorted by class and lesson so everyone finds their own rows quickly
+fn table(substitutions: &[&Substitution], link: &str, filter: &Filter) -> String {
+    let mut rows = substitutions.to_vec();
+    rows.sort_by(|a, b| (&a.class, a.lesson).cmp(&(&b.class, b.lesson)));
+
+    let caption = match filter.class {
+        Some(class) => format!("Zastępstwa klasy {}", escape(class)),
+        None => "Wszystkie zastępstwa".to_string(),
+    };
+    let mut html = String::new();
+    if rows.is_empty() {
+        html.push_str("<p>Brak zastępstw.</p>\n");
+    } else {
+        html.push_str(&format!(
+            "<table>\n<caption>{}</caption>\n<thead>\n<tr><th scope=\"col\">Lekcja</th><th scope=\"col\">Klasa</th><th scope=\"col\">Przedmiot</th><th scope=\"col\">Nieobecny</th><th scope=\"col\">Zastępca</th><th scope=\"col\">Sala</th><th scope=\"col\">Uwagi</th></tr>\n</thead>\n<tbody>\n",
+            caption
+        ));
+        for record in rows {
+            let class = match &record.group {
+                Some(group) => format!("{} gr. {}", record.class, group),
+                None => record.class.clone(),
+            };
+            // Notes usually say what happened already, only a cancellation is always spelled out
+            let note = match &record.note {
+                Some(note) if record.kind == Kind::Cancelled => {
+                    format!("{}: {}", kind_name(record.kind), note)
+                }
+                Some(note) => note.clone(),
+                None => kind_name(record.kind).to_string(),
+            };
+            html.push_str(&format!(
+                "<tr{}><th scope=\"row\">{}</th><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>\n",
+                if record.kind == Kind::Cancelled {
+                    " class=\"cancelled\""
+                } else {
+                    ""
+                },
+                record.lesson,
+                escape(&class),
+                escape(record.subject.as_deref().unwrap_or("-")),
+                escape(&record.absent_teacher),
+                escape(record.substitute_teacher.as_deref().unwrap_or("-")),
+                escape(record.room.as_deref().unwrap_or("-")),
+                escape(&note),
+            ));
+        }
+        html.push_str("</tbody>\n</table>\n");
+    }
+    html.push_str(&format!(
+        "<p><a href=\"{}\">Oryginalny plik PDF</a></p>\n",
+        escape(link)
+    ));
+    html
+}
+
+// When the table can't be read, the PDF is still there
+fn pdf_fallback(link: &str) -> String {
+    let link = escape(link);
+    format!(
+        "<p role=\"alert\">Nie udało się odczytać zastępstw z pliku PDF.</p>\n<p><a href=\"{link}\">Otwórz plik PDF</a></p>\n<object data=\"{link}\" type=\"application/pdf\" width=\"100%\" height=\"600\" aria-label=\"Plik PDF z zastępstwami\"></object>\n"
+    )
+}
+
+fn error_message(error: &ZastepstwaError) -> String {
+    format!("<p role=\"alert\">{}</p>\n", escape(&error.to_string()))
+}
+
+fn html(status: StatusCode, body: String) -> HttpResponse {
+    HttpResponse::build(status)
+        .content_type("text/html; charset=utf-8")
+        .body(body)
+}
+
+#[derive(Deserialize)]
+struct PlanQuery {
+    class: Option<String>,
+    group: Option<String>,
+}
+
+// The substitutions as a page that's easy to read on a phone
+// /plan/10.10.2022
+// /plan/today?class=3TI
+#[get("/plan/{date}")]
+async fn plan_page(
+    state: web::Data<AppState>,
+    path: web::Path<String>,
+    query: web::Query<PlanQuery>,
+) -> HttpResponse {
+    let filter = Filter {
+        class: query
+            .class
+            .as_deref()
+            .filter(|class| !class.trim().is_empty()),
+        group: query
+            .group
+            .as_deref()
+            .filter(|group| !group.trim().is_empty()),
+    };
+    let holidays = &state.config.school.holidays;
+    let today = chrono::Local::now().date_naive();
+    // The date the navigation starts from, even if there are no substitutions for it
+    let (shown, date) = match path.to_lowercase().as_str() {
+        "today" => (today, resolve_when("today")),
+        "tomorrow" => (today + Duration::days(1), resolve_when("tomorrow")),
+        text => match Date::parse(text) {
+            Some(date) => match NaiveDate::from_ymd_opt(date.year, date.month, date.day) {
+                Some(shown) => (shown, Ok(date)),
+                None => (today, Err(ZastepstwaError::InvalidDate)),
+            },
+            None => (
+                today,
+                Err(ZastepstwaError::InvalidParameter(
+                    "Nieprawidłowa data w adresie".to_string(),
+                )),
+            ),
+        },
+    };
+
+    let link = match date {
+        Ok(date) => state.ready_file(&date).await.map(|ready| ready.link),
+        Err(e) => Err(e),
+    };
+    let link = match link {
+        Ok(link) => link,
+        Err(e) => {
+            return html(
+                e.status_code(),
+                page(holidays, shown, &filter, &error_message(&e)),
+            )
+        }
+    };
+
+    let file = state
+        .config
+        .cached_pdf(&shown.format("%d.%m.%Y").to_string());
+    let body = match state.parsed.read(&file).await {
+        Ok(substitutions) => {
+            let rows: Vec<&Substitution> = substitutions
+                .iter()
+                .filter(|record| match filter.class {
+                    Some(class) => record.is_for(class, filter.group),
+                    None => true,
+                })
+                .collect();
+            table(&rows, &link, &filter)
+        }
+        Err(ZastepstwaError::ParseFailed) => pdf_fallback(&link),
+        Err(e) => {
+            return html(
+                e.status_code(),
+                page(holidays, shown, &filter, &error_message(&e)),
+            );
+        }
+    };
+    html(StatusCode::OK, page(holidays, shown, &filter, &body))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders_escaped_table() {
+        let records = crate::parser::parse(include_bytes!("../tests/fixtures/basic.pdf")).unwrap();
+        let mut records: Vec<&Substitution> = records.iter().collect();
+        let mut broken = records[0].clone();
+        broken.note = Some("<script>".to_string());
+        records.push(&broken);
+        let filter = Filter {
+            class: Some("3TI"),
+            group: Some("1"),
+        };
+        let body = table(&records, "http://localhost/files/10.10.2022.pdf", &filter);
+        assert!(body.contains("<caption>Zastępstwa klasy 3TI</caption>"));
+        assert!(body.contains("&lt;script&gt;"));
+        assert!(!body.contains("<script>"));
+        assert!(body.contains("<tr class=\"cancelled\">"));
+
+        // Monday's navigation goes back to Friday
+        let monday = NaiveDate::from_ymd_opt(2022, 10, 10).unwrap();
+        let page = page(&[], monday, &filter, &body);
+        assert!(page.contains("href=\"/plan/07.10.2022?class=3TI&amp;group=1\""));
+        assert!(page.contains("href=\"/plan/11.10.2022?class=3TI&amp;group=1\""));
+    }
+}
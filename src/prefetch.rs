@@ -0,0 +1,114 @@
+use actix_web::web;
+use chrono::{Duration, Local, NaiveDate, NaiveDateTime, Timelike};
+
+use crate::calendar;
+use crate::config::Holiday;
+use crate::error::ZastepstwaError;
+use crate::state::AppState;
+
+// How often to refresh at a given moment. The school publishes the plan for the next day in the
+// afternoon and evening and fixes it in the morning, nothing happens at night or before a free day.
+pub fn interval(holidays: &[Holiday], now: NaiveDateTime) -> Duration {
+    let today = now.date();
+    let school_today = calendar::is_school_day(holidays, today);
+    let school_tomorrow = calendar::is_school_day(holidays, today + Duration::days(1));
+    let minutes = match now.hour() {
+        0..=5 | 22..=23 => 60,
+        // Last-minute changes before and during the first lessons
+        6..=8 if school_today => 5,
+        9..=15 if school_today => 15,
+        // The plan for tomorrow is usually published now
+        16..=21 if school_tomorrow => 10,
+        // Weekends and breaks
+        _ => 180,
+    };
+    Duration::minutes(minutes)
+}
+
+// Today (if there are lessons) and the next school day
+pub fn dates(holidays: &[Holiday], today: NaiveDate) -> Vec<NaiveDate> {
+    let mut dates = Vec::new();
+    if calendar::is_school_day(holidays, today) {
+        dates.push(today);
+    }
+    dates.push(calendar::next_school_day(holidays, today));
+    dates
+}
+
+// Download a date like a request would, but always ask the school website and don't count it as a hit
+async fn refresh(state: &AppState, date: NaiveDate) {
+    let name = date.format("%d.%m.%Y").to_string();
+    match state.refresh(date).await {
+        Ok(_) => {
+            log::info!("Prefetched {}", name);
+            // Parse it now, so the first request for the table doesn't have to wait
+            let _ = state.parsed.read(&state.config.cached_pdf(&name)).await;
+        }
+        Err(ZastepstwaError::NotPublished(_)) => log::info!("{} isn't published yet", name),
+        Err(e) => log::warn!("Error while prefetching {}: {}", name, e),
+    }
+}
+
+// Keeps today and the next school day fresh, so nobody has to wait for the school website
+pub async fn run(state: web::Data<AppState>) {
+    let mut last: Option<NaiveDateTime> = None;
+    loop {
+        let now = Local::now().naive_local();
+        let holidays = &state.config.school.holidays;
+        let every = interval(holidays, now);
+        let due = last.is_none_or(|last| now - last >= every);
+        if due {
+            // During maintenance nothing is served, so there's nothing to keep fresh. The skipped round
+            // still counts, otherwise the loop would wake up every second until maintenance ends.
+            if state.maintenance.active_message().is_none() {
+                for date in dates(holidays, now.date()) {
+                    refresh(&state, date).await;
+                }
+            }
+            last = Some(now);
+        }
+
+        // Wake up at least every full hour, the interval may be shorter by then
+        let since = last.map_or(Duration::zero(), |last| now - last);
+        let until_hour = Duration::seconds(3600 - (now.minute() * 60 + now.second()) as i64);
+        let wait =
+            (every - since).clamp(Duration::seconds(1), until_hour.max(Duration::seconds(1)));
+        tokio::time::sleep(wait.to_std().unwrap_or_default()).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn adaptive_interval() {
+        let at = |day, hour| {
+            NaiveDate::from_ymd_opt(2023, 10, day)
+                .unwrap()
+                .and_hms_opt(hour, 0, 0)
+                .unwrap()
+        };
+        // Monday 9.10.2023
+        assert_eq!(interval(&[], at(9, 7)), Duration::minutes(5));
+        assert_eq!(interval(&[], at(9, 12)), Duration::minutes(15));
+        assert_eq!(interval(&[], at(9, 18)), Duration::minutes(10));
+        assert_eq!(interval(&[], at(9, 23)), Duration::minutes(60));
+        // Friday evening and Saturday, Sunday evening is before a school day again
+        assert_eq!(interval(&[], at(13, 18)), Duration::minutes(180));
+        assert_eq!(interval(&[], at(14, 10)), Duration::minutes(180));
+        assert_eq!(interval(&[], at(15, 18)), Duration::minutes(10));
+
+        let date = |day| NaiveDate::from_ymd_opt(2023, 10, day).unwrap();
+        assert_eq!(dates(&[], date(13)), [date(13), date(16)]);
+        assert_eq!(dates(&[], date(14)), [date(16)]);
+
+        // A free Monday: nothing to publish on Sunday evening, Tuesday is next
+        let holidays = [Holiday {
+            from: date(16),
+            to: date(16),
+        }];
+        assert_eq!(interval(&holidays, at(15, 18)), Duration::minutes(180));
+        assert_eq!(dates(&holidays, date(13)), [date(13), date(17)]);
+    }
+}
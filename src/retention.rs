@@ -0,0 +1,322 @@
+use actix_web::web;
+use chrono::{Datelike, Local, NaiveDate};
+use std::fmt;
+
+use crate::config::{Config, RetentionConfig};
+use crate::index::{Entry, Index};
+use crate::state::AppState;
+
+// Why a whole date is removed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Reason {
+    // Older than retention.max_age_days
+    Age,
+    // The cache is bigger than retention.max_size_mb, the oldest dates go first
+    Size,
+    // Asked for on the command line with --before
+    Before,
+}
+
+// Something the retention rules want to delete
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Removal {
+    // The current copy and every version of a date
+    Date {
+        date: NaiveDate,
+        bytes: u64,
+        reason: Reason,
+    },
+    // Older versions of a date, the newest ones and the current copy stay
+    Revisions {
+        date: NaiveDate,
+        numbers: Vec<u32>,
+        bytes: u64,
+    },
+}
+
+impl Removal {
+    pub fn bytes(&self) -> u64 {
+        match self {
+            Removal::Date { bytes, .. } | Removal::Revisions { bytes, .. } => *bytes,
+        }
+    }
+}
+
+impl fmt::Display for Removal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Removal::Date {
+                date,
+                bytes,
+                reason,
+            } => write!(
+                f,
+                "{} whole date, {} B ({})",
+                date.format("%d.%m.%Y"),
+                bytes,
+                match reason {
+                    Reason::Age => "too old",
+                    Reason::Size => "cache too big",
+                    Reason::Before => "before the given date",
+                }
+            ),
+            Removal::Revisions {
+                date,
+                numbers,
+                bytes,
+            } => write!(
+                f,
+                "{} version(s) {}, {} B (too many versions)",
+                date.format("%d.%m.%Y"),
+                numbers
+                    .iter()
+                    .map(|number| number.to_string())
+                    .collect::<Vec<_>>()
+                    .join(", "),
+                bytes
+            ),
+        }
+    }
+}
+
+// The school year starts on the 1st of September
+fn school_year_start(today: NaiveDate) -> NaiveDate {
+    let year = if today.month() >= 9 {
+        today.year()
+    } else {
+        today.year() - 1
+    };
+    NaiveDate::from_ymd_opt(year, 9, 1).unwrap()
+}
+
+// The current copy and every version on disk
+fn entry_size(entry: &Entry) -> u64 {
+    entry.size
+        + entry
+            .history
+            .iter()
+            .map(|revision| revision.size)
+            .sum::<u64>()
+}
+
+// What the rules would delete from the cache. Today and upcoming days are never removed whole,
+// and neither is the current school year if keep_school_year is set.
+pub fn plan(
+    rules: &RetentionConfig,
+    entries: &[(NaiveDate, Entry)],
+    today: NaiveDate,
+) -> Vec<Removal> {
+    let protected = |date: NaiveDate| {
+        date >= today || (rules.keep_school_year && date >= school_year_start(today))
+    };
+    let mut removals = Vec::new();
+    // Dates that stay, oldest first: (date, size after trimming versions, size of everything)
+    let mut kept = Vec::new();
+    for (date, entry) in entries {
+        let date = *date;
+        let size = entry_size(entry);
+        let too_old = rules
+            .max_age_days
+            .is_some_and(|days| (today - date).num_days() > days as i64);
+        if too_old && !protected(date) {
+            removals.push(Removal::Date {
+                date,
+                bytes: size,
+                reason: Reason::Age,
+            });
+            continue;
+        }
+
+        let mut remaining = size;
+        if let Some(keep) = rules.keep_revisions {
+            let old: Vec<_> = entry.history.iter().rev().skip(keep as usize).collect();
+            if !old.is_empty() {
+                let bytes = old.iter().map(|revision| revision.size).sum();
+                remaining -= bytes;
+                removals.push(Removal::Revisions {
+                    date,
+                    numbers: old.iter().rev().map(|revision| revision.number).collect(),
+                    bytes,
+                });
+            }
+        }
+        kept.push((date, remaining, size));
+    }
+
+    if let Some(max_mb) = rules.max_size_mb {
+        let max = max_mb * 1024 * 1024;
+        let mut total: u64 = kept.iter().map(|(_, remaining, _)| remaining).sum();
+        for (date, remaining, size) in kept {
+            if total <= max {
+                break;
+            }
+            if protected(date) {
+                continue;
+            }
+            // The whole date goes, so trimming its versions is part of that
+            removals.retain(
+                |removal| !matches!(removal, Removal::Revisions { date: trimmed, .. } if *trimmed == date),
+            );
+            removals.push(Removal::Date {
+                date,
+                bytes: size,
+                reason: Reason::Size,
+            });
+            total -= remaining;
+        }
+    }
+    removals
+}
+
+// Every date before a date, for `cache prune --before`
+pub fn before(entries: &[(NaiveDate, Entry)], before: NaiveDate) -> Vec<Removal> {
+    entries
+        .iter()
+        .filter(|(date, _)| *date < before)
+        .map(|(date, entry)| Removal::Date {
+            date: *date,
+            bytes: entry_size(entry),
+            reason: Reason::Before,
+        })
+        .collect()
+}
+
+// Files that are already gone are fine
+async fn remove_file(path: &std::path::Path) -> std::io::Result<()> {
+    match tokio::fs::remove_file(path).await {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+// Delete the files first and update the index after, so the index never points at files that
+// are still there but forgotten
+pub async fn apply(config: &Config, index: &Index, removals: &[Removal]) -> std::io::Result<()> {
+    for removal in removals {
+        match removal {
+            Removal::Date { date, .. } => {
+                let name = date.format("%d.%m.%Y").to_string();
+                remove_file(&config.cached_pdf(&name)).await?;
+                match tokio::fs::remove_dir_all(config.cached_revisions(&name)).await {
+                    Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e),
+                    _ => {}
+                }
+                index.forget(*date).await?;
+            }
+            Removal::Revisions { date, numbers, .. } => {
+                let name = date.format("%d.%m.%Y").to_string();
+                for number in numbers {
+                    remove_file(&config.cached_revision(&name, *number)).await?;
+                }
+                index.forget_revisions(*date, numbers).await?;
+            }
+        }
+    }
+    Ok(())
+}
+
+// Applies the retention rules every few hours while the server runs
+pub async fn janitor(state: web::Data<AppState>) {
+    let every = std::time::Duration::from_secs(state.config.retention.interval_hours * 3600);
+    loop {
+        let removals = plan(
+            &state.config.retention,
+            &state.index.entries(),
+            Local::now().date_naive(),
+        );
+        for removal in &removals {
+            log::info!("Removing from the cache: {}", removal);
+        }
+        if let Err(e) = apply(&state.config, &state.index, &removals).await {
+            log::error!("Error while cleaning up the cache: {}", e);
+        }
+        tokio::time::sleep(every).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::index::{Revision, UpstreamStatus};
+    use crate::source::Validators;
+
+    fn entry(versions: u32) -> Entry {
+        let now = Local::now();
+        Entry {
+            first_seen: now,
+            last_checked: now,
+            last_changed: now,
+            upstream_status: UpstreamStatus::Ok,
+            sha256: String::new(),
+            size: 1024 * 1024,
+            revisions: versions,
+            history: (1..=versions)
+                .map(|number| Revision {
+                    number,
+                    sha256: String::new(),
+                    size: 1024 * 1024,
+                    fetched_at: now,
+                })
+                .collect(),
+            hits: 0,
+            validators: Validators::default(),
+        }
+    }
+
+    #[test]
+    fn rules() {
+        let date = |day, month, year| NaiveDate::from_ymd_opt(year, month, day).unwrap();
+        let today = date(10, 10, 2023);
+        // 2 MB each, 3 MB for the date with 2 versions
+        let entries = vec![
+            (date(10, 5, 2022), entry(1)),
+            (date(12, 6, 2023), entry(1)),
+            (date(11, 9, 2023), entry(2)),
+            (date(11, 10, 2023), entry(1)),
+        ];
+
+        let rules = RetentionConfig {
+            max_age_days: Some(365),
+            keep_revisions: Some(1),
+            ..RetentionConfig::default()
+        };
+        assert_eq!(
+            plan(&rules, &entries, today),
+            [
+                Removal::Date {
+                    date: date(10, 5, 2022),
+                    bytes: 2 * 1024 * 1024,
+                    reason: Reason::Age,
+                },
+                Removal::Revisions {
+                    date: date(11, 9, 2023),
+                    numbers: vec![1],
+                    bytes: 1024 * 1024,
+                },
+            ]
+        );
+
+        // 9 MB in total, the current school year and tomorrow stay even if it's still too much
+        let rules = RetentionConfig {
+            max_size_mb: Some(4),
+            ..RetentionConfig::default()
+        };
+        let removals = plan(&rules, &entries, today);
+        assert_eq!(removals.len(), 2);
+        assert!(removals.iter().all(|removal| matches!(
+            removal,
+            Removal::Date {
+                reason: Reason::Size,
+                ..
+            }
+        )));
+        let rules = RetentionConfig {
+            keep_school_year: false,
+            ..rules
+        };
+        assert_eq!(plan(&rules, &entries, today).len(), 3);
+
+        assert!(plan(&RetentionConfig::default(), &entries, today).is_empty());
+        assert_eq!(before(&entries, date(1, 9, 2023)).len(), 2);
+    }
+}
@@ -0,0 +1,85 @@
+use actix_files::NamedFile;
+use actix_web::{get, web, HttpResponse};
+use serde::{Deserialize, Serialize};
+
+use crate::error::ZastepstwaError;
+use crate::index::Revision;
+use crate::state::AppState;
+use crate::Date;
+
+#[derive(Deserialize)]
+struct RevisionPath {
+    day: u32,
+    month: u32,
+    year: i32,
+    number: u32,
+}
+
+#[derive(Serialize)]
+struct RevisionResponse<'a> {
+    // number, fetched_at, sha256 and size
+    #[serde(flatten)]
+    revision: &'a Revision,
+    link: String,
+}
+
+#[derive(Serialize)]
+struct RevisionsResponse<'a> {
+    code: u16,
+    date: &'a str,
+    latest: u32,
+    revisions: Vec<RevisionResponse<'a>>,
+}
+
+// Every version of the PDF for a date, oldest first
+// /revisions/10.10.2022
+#[get("/revisions/{day}.{month}.{year}")]
+async fn list_revisions(
+    state: web::Data<AppState>,
+    date: web::Path<Date>,
+) -> Result<HttpResponse, ZastepstwaError> {
+    let naive_date = chrono::NaiveDate::from_ymd_opt(date.year, date.month, date.day)
+        .ok_or(ZastepstwaError::InvalidDate)?;
+    let date = naive_date.format("%d.%m.%Y").to_string();
+    let entry = state
+        .index
+        .get(naive_date)
+        .ok_or_else(|| ZastepstwaError::NotPublished(date.clone()))?;
+
+    let revisions = entry
+        .history
+        .iter()
+        .map(|revision| RevisionResponse {
+            revision,
+            link: state.config.revision_link(&date, revision.number),
+        })
+        .collect();
+    Ok(HttpResponse::Ok().json(RevisionsResponse {
+        code: 200,
+        date: &date,
+        latest: entry.revisions,
+        revisions,
+    }))
+}
+
+// A specific version of the PDF for a date
+// /files/10.10.2022/2.pdf
+#[get("/files/{day}.{month}.{year}/{number}.pdf")]
+async fn revision_file(
+    state: web::Data<AppState>,
+    path: web::Path<RevisionPath>,
+) -> Result<NamedFile, ZastepstwaError> {
+    let naive_date = chrono::NaiveDate::from_ymd_opt(path.year, path.month, path.day)
+        .ok_or(ZastepstwaError::InvalidDate)?;
+    let date = naive_date.format("%d.%m.%Y").to_string();
+    let file = state.config.cached_revision(&date, path.number);
+    NamedFile::open_async(&file).await.map_err(|e| {
+        if e.kind() != std::io::ErrorKind::NotFound {
+            log::error!("Error while opening {}: {}", file.display(), e);
+        }
+        ZastepstwaError::NotFound(format!(
+            "Nie ma wersji {} zastępstw na dzień {}",
+            path.number, date
+        ))
+    })
+}
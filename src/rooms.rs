@@ -0,0 +1,159 @@
+use actix_web::{get, web, HttpResponse};
+use chrono::Datelike;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+use crate::error::ZastepstwaError;
+use crate::requested_date;
+use crate::state::AppState;
+use crate::timetable::{EffectiveLesson, Status, Timetable};
+
+// Who is in a room during a lesson
+#[derive(Debug, Clone, Serialize)]
+pub struct Occupant {
+    pub class: String,
+    pub group: Option<String>,
+    pub teacher: Option<String>,
+    pub subject: Option<String>,
+}
+
+// Room -> lesson number -> everyone in the room during that lesson
+pub type Occupancy = BTreeMap<String, BTreeMap<u32, Vec<Occupant>>>;
+
+// Rooms used during a day, from the plan with the substitutions applied. Cancelled lessons don't use a room
+// and moved lessons take place in the room from the note.
+pub fn occupancy(lessons: &[EffectiveLesson]) -> Occupancy {
+    let mut rooms = Occupancy::new();
+    for lesson in lessons {
+        if lesson.status == Status::Cancelled {
+            continue;
+        }
+        let Some(room) = &lesson.room else {
+            continue;
+        };
+        rooms
+            .entry(room.clone())
+            .or_default()
+            .entry(lesson.lesson)
+            .or_default()
+            .push(Occupant {
+                class: lesson.class.clone(),
+                group: lesson.group.clone(),
+                teacher: lesson.teacher.clone(),
+                subject: lesson.subject.clone(),
+            });
+    }
+    rooms
+}
+
+// Rooms are compared without case, "SG" and "sg" are the same room
+fn same_room(a: &str, b: &str) -> bool {
+    a.trim().eq_ignore_ascii_case(b.trim())
+}
+
+#[derive(Deserialize)]
+struct RoomsQuery {
+    date: Option<String>,
+    when: Option<String>,
+    // Only this room
+    room: Option<String>,
+    // List the rooms that are free during this lesson
+    lesson: Option<u32>,
+}
+
+#[derive(Serialize)]
+struct RoomResponse<'a> {
+    room: &'a str,
+    lessons: &'a BTreeMap<u32, Vec<Occupant>>,
+}
+
+#[derive(Serialize)]
+struct RoomsResponse<'a> {
+    code: u16,
+    // None when there are no substitutions for the date
+    link: Option<String>,
+    date: String,
+    rooms: Vec<RoomResponse<'a>>,
+    // Only with ?lesson=
+    #[serde(skip_serializing_if = "Option::is_none")]
+    lesson: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    free: Option<Vec<&'a String>>,
+}
+
+// Which rooms are used during which lesson, and which are free
+// /api/rooms?date=10.10.2022
+// /api/rooms?when=today&lesson=5
+#[get("/api/rooms")]
+async fn room_occupancy(
+    state: web::Data<AppState>,
+    timetable: web::Data<Timetable>,
+    query: web::Query<RoomsQuery>,
+) -> Result<HttpResponse, ZastepstwaError> {
+    if query.lesson.is_some() && state.config.school.rooms.is_empty() {
+        return Err(ZastepstwaError::NotFound(
+            "Lista sal nie jest skonfigurowana".to_string(),
+        ));
+    }
+    // Without the normal plan every room would look free, except the ones in the substitutions
+    if query.lesson.is_some() && timetable.is_empty() {
+        return Err(ZastepstwaError::NotFound(
+            "Plan lekcji nie został zaimportowany".to_string(),
+        ));
+    }
+    let date = requested_date(query.date.as_deref(), query.when.as_deref())?;
+    let day = state.for_plan(&date).await?;
+    let used = occupancy(&timetable.effective(day.date.weekday(), &day.substitutions));
+
+    // Every configured room, even the ones nobody uses, and rooms from the PDF that aren't in the list
+    let mut rooms: Occupancy = state
+        .config
+        .school
+        .rooms
+        .iter()
+        .map(|room| (room.clone(), BTreeMap::new()))
+        .collect();
+    for (room, lessons) in used {
+        let name = state
+            .config
+            .school
+            .rooms
+            .iter()
+            .find(|known| same_room(known, &room))
+            .cloned()
+            .unwrap_or(room);
+        let room = rooms.entry(name).or_default();
+        for (lesson, occupants) in lessons {
+            room.entry(lesson).or_default().extend(occupants);
+        }
+    }
+    if let Some(room) = &query.room {
+        rooms.retain(|name, _| same_room(name, room));
+    }
+
+    let free = query.lesson.map(|lesson| {
+        state
+            .config
+            .school
+            .rooms
+            .iter()
+            .filter(|room| {
+                rooms
+                    .get(*room)
+                    .is_some_and(|lessons| !lessons.contains_key(&lesson))
+            })
+            .collect()
+    });
+    let response = RoomsResponse {
+        code: 200,
+        link: day.link,
+        date: day.name,
+        rooms: rooms
+            .iter()
+            .map(|(room, lessons)| RoomResponse { room, lessons })
+            .collect(),
+        lesson: query.lesson,
+        free,
+    };
+    Ok(HttpResponse::Ok().json(response))
+}
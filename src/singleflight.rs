@@ -0,0 +1,95 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::hash::Hash;
+use std::sync::{Arc, Mutex};
+use tokio::sync::OnceCell;
+
+// Makes sure only one call per key runs at a time. Everyone who asks for the same key
+// while a call is running waits for it and gets a copy of its result.
+pub struct SingleFlight<K, V> {
+    calls: Mutex<HashMap<K, Arc<OnceCell<V>>>>,
+}
+
+impl<K, V> Default for SingleFlight<K, V> {
+    fn default() -> Self {
+        SingleFlight {
+            calls: Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+impl<K: Eq + Hash + Clone, V: Clone> SingleFlight<K, V> {
+    pub async fn run<F, Fut>(&self, key: K, f: F) -> V
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = V>,
+    {
+        // Join the running call or start a new one
+        let cell = self
+            .calls
+            .lock()
+            .unwrap()
+            .entry(key.clone())
+            .or_default()
+            .clone();
+        // If the caller running f goes away (for example the client disconnects), one of the waiting callers takes over
+        let value = cell.get_or_init(f).await.clone();
+
+        // Forget the finished call, so the next request starts a fresh one
+        let mut calls = self.calls.lock().unwrap();
+        if calls.get(&key).is_some_and(|call| Arc::ptr_eq(call, &cell)) {
+            calls.remove(&key);
+        }
+        value
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn one_call_per_key() {
+        let flight = Arc::new(SingleFlight::<u32, u32>::default());
+        let calls = Arc::new(AtomicUsize::new(0));
+        let callers: Vec<_> = (0..10)
+            .map(|_| {
+                let flight = flight.clone();
+                let calls = calls.clone();
+                tokio::spawn(async move {
+                    flight
+                        .run(1, move || async move {
+                            calls.fetch_add(1, Ordering::SeqCst);
+                            tokio::time::sleep(Duration::from_millis(50)).await;
+                            7
+                        })
+                        .await
+                })
+            })
+            .collect();
+        for caller in callers {
+            assert_eq!(caller.await.unwrap(), 7);
+        }
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+        // The finished call is forgotten, the next one runs again
+        assert_eq!(flight.run(1, || async { 8 }).await, 8);
+
+        // The caller running the call goes away, the one waiting for it takes over
+        let first = tokio::spawn({
+            let flight = flight.clone();
+            async move { flight.run(2, std::future::pending::<u32>).await }
+        });
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        let second = tokio::spawn({
+            let flight = flight.clone();
+            async move { flight.run(2, || async { 9 }).await }
+        });
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        first.abort();
+        let second = tokio::time::timeout(Duration::from_secs(1), second).await;
+        assert_eq!(second.expect("nobody took over").unwrap(), 9);
+        assert!(flight.calls.lock().unwrap().is_empty());
+    }
+}
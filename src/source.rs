@@ -0,0 +1,148 @@
+use async_trait::async_trait;
+use chrono::NaiveDate;
+use reqwest::header::{CONTENT_TYPE, ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::sync::Arc;
+
+use crate::config::{SourceKind, UpstreamConfig};
+
+// What a source returned for a date
+pub enum Fetched {
+    // The substitution document was published, with the validators to send next time
+    Document {
+        bytes: Vec<u8>,
+        validators: Validators,
+        // As the server sent it, checked before the document replaces the cached copy
+        content_type: Option<String>,
+    },
+    // The document didn't change since the version described by the validators we sent
+    NotModified,
+    // The school hasn't published anything for this date (yet)
+    NotPublished,
+}
+
+// ETag and Last-Modified of the cached copy, used to ask the server if anything changed
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Validators {
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+}
+
+#[derive(Debug)]
+pub enum SourceError {
+    // The school website couldn't be reached or the download broke off
+    Offline(String),
+    // The school website answered with a status we don't know what to do with
+    UnexpectedStatus(u16),
+    // The server answered, but the document failed validation (an error page, a truncated file, ...)
+    Rejected(String),
+}
+
+impl fmt::Display for SourceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SourceError::Offline(reason) => write!(f, "upstream is offline: {}", reason),
+            SourceError::UnexpectedStatus(status) => {
+                write!(f, "upstream returned unexpected status {}", status)
+            }
+            SourceError::Rejected(reason) => {
+                write!(f, "upstream returned an invalid document: {}", reason)
+            }
+        }
+    }
+}
+
+impl std::error::Error for SourceError {}
+
+// Where the substitutions come from. Every backend the school uses (or used) is a separate implementation,
+// the handlers only care about getting a document for a date.
+#[async_trait]
+pub trait SubstitutionSource: Send + Sync {
+    // Short name used in logs
+    fn name(&self) -> &'static str;
+
+    // Sources that support conditional requests return NotModified if the validators still match
+    async fn fetch(&self, date: NaiveDate, validators: &Validators)
+        -> Result<Fetched, SourceError>;
+}
+
+// Build the source selected in the config file
+pub fn from_config(config: &UpstreamConfig) -> Arc<dyn SubstitutionSource> {
+    match config.kind {
+        SourceKind::PdfUrl => Arc::new(PdfUrlSource::new(&config.url)),
+    }
+}
+
+// The original scheme: one PDF per day at {base_url}{dd.mm.yyyy}.pdf
+pub struct PdfUrlSource {
+    base_url: String,
+    client: reqwest::Client,
+}
+
+impl PdfUrlSource {
+    pub fn new(base_url: &str) -> PdfUrlSource {
+        PdfUrlSource {
+            base_url: base_url.to_string(),
+            client: reqwest::Client::new(),
+        }
+    }
+}
+
+#[async_trait]
+impl SubstitutionSource for PdfUrlSource {
+    fn name(&self) -> &'static str {
+        "pdf_url"
+    }
+
+    async fn fetch(
+        &self,
+        date: NaiveDate,
+        validators: &Validators,
+    ) -> Result<Fetched, SourceError> {
+        let url = format!("{}{}.pdf", self.base_url, date.format("%d.%m.%Y"));
+        let mut request = self.client.get(&url);
+        if let Some(etag) = &validators.etag {
+            request = request.header(IF_NONE_MATCH, etag);
+        }
+        if let Some(last_modified) = &validators.last_modified {
+            request = request.header(IF_MODIFIED_SINCE, last_modified);
+        }
+        let response = request
+            .send()
+            .await
+            .map_err(|e| SourceError::Offline(e.to_string()))?;
+
+        // Match different status codes from the server and act accordingly
+        match response.status().as_u16() {
+            200 => {
+                let header = |name| {
+                    response
+                        .headers()
+                        .get(name)
+                        .and_then(|value| value.to_str().ok())
+                        .map(str::to_string)
+                };
+                let validators = Validators {
+                    etag: header(ETAG),
+                    last_modified: header(LAST_MODIFIED),
+                };
+                let content_type = header(CONTENT_TYPE);
+                let bytes = response
+                    .bytes()
+                    .await
+                    .map_err(|e| SourceError::Offline(e.to_string()))?;
+                Ok(Fetched::Document {
+                    bytes: bytes.to_vec(),
+                    validators,
+                    content_type,
+                })
+            }
+            // Our copy is still up to date
+            304 => Ok(Fetched::NotModified),
+            // A 404 means that there are currently no substitutions available
+            404 => Ok(Fetched::NotPublished),
+            status => Err(SourceError::UnexpectedStatus(status)),
+        }
+    }
+}
@@ -0,0 +1,309 @@
+use chrono::NaiveDate;
+use std::sync::Arc;
+
+use crate::cache;
+use crate::calendar;
+use crate::config::{Config, Holiday};
+use crate::error::ZastepstwaError;
+use crate::index::{Index, UpstreamStatus};
+use crate::maintenance::MaintenanceState;
+use crate::parser::Substitution;
+use crate::singleflight::SingleFlight;
+use crate::source::{self, Fetched, SourceError, SubstitutionSource, Validators};
+use crate::substitutions::Parsed;
+use crate::validate;
+use crate::Date;
+
+// Downloads currently in progress, so requests for the same date share one download
+pub type Downloads = SingleFlight<NaiveDate, Result<String, ZastepstwaError>>;
+
+// A cached copy that can be served
+pub struct Ready {
+    pub link: String,
+    pub date: NaiveDate,
+    // dd.mm.yyyy, the name of the cached file
+    pub name: String,
+}
+
+// A date as the views built on the normal plan see it, the link is None if nothing was published yet
+pub struct PlanDay {
+    pub link: Option<String>,
+    pub date: NaiveDate,
+    // dd.mm.yyyy
+    pub name: String,
+    pub substitutions: Arc<Vec<Substitution>>,
+}
+
+// Everything the handlers, the background tasks and the command line tools share
+pub struct AppState {
+    pub config: Config,
+    pub maintenance: MaintenanceState,
+    pub source: Arc<dyn SubstitutionSource>,
+    pub downloads: Downloads,
+    pub index: Index,
+    pub parsed: Parsed,
+}
+
+impl AppState {
+    pub async fn open(config: Config, maintenance: MaintenanceState) -> std::io::Result<AppState> {
+        let source = source::from_config(&config.upstream);
+        log::info!("Using source {}", source.name());
+        let index = Index::open(config.cache_index(), &config.cache.dir).await?;
+        Ok(AppState {
+            config,
+            maintenance,
+            source,
+            downloads: Downloads::default(),
+            index,
+            parsed: Parsed::default(),
+        })
+    }
+
+    pub async fn ready_file(&self, date: &Date) -> Result<Ready, ZastepstwaError> {
+        let date = self.servable_date(date)?;
+        self.ready(date).await
+    }
+
+    // The requested date, if it can be served right now
+    fn servable_date(&self, date: &Date) -> Result<NaiveDate, ZastepstwaError> {
+        if let Some(message) = self.maintenance.active_message() {
+            return Err(ZastepstwaError::Maintenance(message));
+        }
+        school_date(&self.config.school.holidays, date)
+    }
+
+    async fn ready(&self, date: NaiveDate) -> Result<Ready, ZastepstwaError> {
+        // Check if the file was checked less than X minutes ago. If it was, return the link to the file. If it wasn't, try to download the new one.
+        // If it fails, return the link to the old file. If it succeeds, return the link to the new file.
+        let link = match self.fresh_link(date) {
+            Some(link) => link,
+            // If we got here, it means that the file doesn't exist or it's too old. We need to download the new one.
+            // If another request is already downloading this date, wait for it instead of downloading it again.
+            None => self.refresh(date).await?,
+        };
+
+        self.index.record_hit(date);
+        Ok(Ready {
+            link,
+            date,
+            name: date.format("%d.%m.%Y").to_string(),
+        })
+    }
+
+    // The link to the cached copy, if the school website was asked about it less than time_min minutes ago
+    pub fn fresh_link(&self, date: NaiveDate) -> Option<String> {
+        let name = date.format("%d.%m.%Y").to_string();
+        let fresh = self.config.cached_pdf(&name).exists()
+            && self
+                .index
+                .get(date)
+                .is_some_and(|entry| entry.is_fresh(self.config.cache.time_min));
+        fresh.then(|| self.config.file_link(&name))
+    }
+
+    // The link and the parsed substitutions for a date. A date without a published PDF simply has no substitutions,
+    // which is what views built on the normal plan want.
+    pub async fn for_plan(&self, date: &Date) -> Result<PlanDay, ZastepstwaError> {
+        let date = self.servable_date(date)?;
+        match self.ready(date).await {
+            Ok(ready) => Ok(PlanDay {
+                substitutions: self
+                    .parsed
+                    .read(&self.config.cached_pdf(&ready.name))
+                    .await?,
+                link: Some(ready.link),
+                date,
+                name: ready.name,
+            }),
+            Err(ZastepstwaError::NotPublished(name)) => Ok(PlanDay {
+                link: None,
+                date,
+                name,
+                substitutions: Arc::new(Vec::new()),
+            }),
+            Err(e) => Err(e),
+        }
+    }
+
+    // Ask the school website about a date, sharing the download with everyone asking at the same time
+    pub async fn refresh(&self, date: NaiveDate) -> Result<String, ZastepstwaError> {
+        let name = date.format("%d.%m.%Y").to_string();
+        self.downloads.run(date, || self.download(date, name)).await
+    }
+
+    async fn download(
+        &self,
+        naive_date: NaiveDate,
+        date: String,
+    ) -> Result<String, ZastepstwaError> {
+        let config = &self.config;
+        let index = &self.index;
+        let source = self.source.as_ref();
+        let filename_pdf = config.cached_pdf(&date);
+        // Only ask "did it change?" if we actually have the old copy
+        let validators = match index.get(naive_date) {
+            Some(entry) if filename_pdf.exists() => entry.validators,
+            _ => Validators::default(),
+        };
+
+        let mut result = source.fetch(naive_date, &validators).await;
+        // A 200 isn't enough, captive portals and error pages answer with one too
+        if let Ok(Fetched::Document {
+            bytes,
+            content_type,
+            ..
+        }) = &mut result
+        {
+            // Loading the PDF is slow CPU work, so it runs on the blocking pool instead of a worker thread
+            let rules = config.validation.clone();
+            let content_type = content_type.clone();
+            let document = std::mem::take(bytes);
+            let checked = tokio::task::spawn_blocking(move || {
+                let checked = validate::check(&rules, &document, content_type.as_deref());
+                (checked, document)
+            })
+            .await;
+            let checked = match checked {
+                Ok((checked, document)) => {
+                    *bytes = document;
+                    checked
+                }
+                // The check catches lopdf's panics itself, so this only happens when the runtime shuts down
+                Err(e) => Err(validate::Rejection::Malformed(e.to_string())),
+            };
+            if let Err(rejection) = checked {
+                // The old copy (if there is one) stays, the next request tries again
+                log::warn!(
+                    "Rejected the download of {} from {}: {}",
+                    date,
+                    source.name(),
+                    rejection
+                );
+                validate::record_rejection();
+                result = Err(SourceError::Rejected(rejection.to_string()));
+            }
+        }
+        let saved = match &result {
+            Ok(Fetched::Document {
+                bytes: filebytes,
+                validators,
+                ..
+            }) => {
+                if let Err(e) = self.save_download(naive_date, &date, filebytes).await {
+                    log::error!("Error while saving {}: {}", date, e);
+                    // The old copy wasn't touched, so it can still be served
+                    if filename_pdf.exists() {
+                        return Ok(config.file_link(&date));
+                    }
+                    return Err(ZastepstwaError::Storage);
+                }
+                index
+                    .record_download(naive_date, filebytes, validators.clone())
+                    .await
+            }
+            // Nothing changed, the cached copy is fresh again
+            Ok(Fetched::NotModified) => {
+                index
+                    .record_check(naive_date, UpstreamStatus::NotModified)
+                    .await
+            }
+            Ok(Fetched::NotPublished) => {
+                index
+                    .record_check(naive_date, UpstreamStatus::NotPublished)
+                    .await
+            }
+            Err(SourceError::Offline(_)) => {
+                index
+                    .record_check(naive_date, UpstreamStatus::Offline)
+                    .await
+            }
+            Err(SourceError::UnexpectedStatus(response_status)) => {
+                index
+                    .record_check(
+                        naive_date,
+                        UpstreamStatus::UnexpectedStatus(*response_status),
+                    )
+                    .await
+            }
+            Err(SourceError::Rejected(_)) => {
+                index
+                    .record_check(naive_date, UpstreamStatus::Rejected)
+                    .await
+            }
+        };
+        if let Err(e) = saved {
+            log::warn!("Error while saving the cache index: {}", e);
+        }
+
+        match result {
+            // Return the link to the file
+            Ok(Fetched::Document { .. }) => Ok(config.file_link(&date)),
+            // If we already have an older version of the file, it's better than nothing
+            _ if filename_pdf.exists() => Ok(config.file_link(&date)),
+            // There are currently no substitutions available
+            Ok(Fetched::NotPublished) => Err(ZastepstwaError::NotPublished(date)),
+            // We never send validators without a cached copy, so the server shouldn't answer like this
+            Ok(Fetched::NotModified) => Err(ZastepstwaError::UpstreamStatus(304)),
+            Err(SourceError::Offline(reason)) => {
+                log::warn!("Source {} is offline: {}", source.name(), reason);
+                Err(ZastepstwaError::UpstreamOffline)
+            }
+            // Return an error if the server returns a different status code
+            Err(SourceError::UnexpectedStatus(response_status)) => {
+                Err(ZastepstwaError::UpstreamStatus(response_status))
+            }
+            Err(SourceError::Rejected(_)) => Err(ZastepstwaError::InvalidDocument),
+        }
+    }
+
+    // Save a new version as the next revision and as the current copy. Nothing is rewritten if the content didn't change.
+    async fn save_download(
+        &self,
+        naive_date: NaiveDate,
+        date: &str,
+        bytes: &[u8],
+    ) -> std::io::Result<()> {
+        let changed = self.index.is_changed(naive_date, bytes);
+        if changed {
+            let revision = self.index.next_revision(naive_date);
+            cache::store(&self.config.cached_revision(date, revision), bytes).await?;
+        }
+        let filename_pdf = self.config.cached_pdf(date);
+        if changed || !filename_pdf.exists() {
+            cache::store(&filename_pdf, bytes).await?;
+        }
+        Ok(())
+    }
+}
+
+// The requested date, if the school can publish substitutions for it
+pub fn school_date(holidays: &[Holiday], date: &Date) -> Result<NaiveDate, ZastepstwaError> {
+    // Check if the date is valid using chrono
+    let naive_date = match NaiveDate::from_ymd_opt(date.year, date.month, date.day) {
+        Some(naive_date) => naive_date,
+        // If it isn't, return an error
+        None => return Err(ZastepstwaError::InvalidDate),
+    };
+
+    // Check if the date is on the winter break (16.01 - 29.01)
+    if calendar::is_winter_break(naive_date) {
+        // If it is, return an error
+        return Err(ZastepstwaError::Holiday(
+            "Jest przerwa zimowa! Możesz odpoczywać!",
+        ));
+    }
+
+    // Check if the date is on the weekend
+    if calendar::is_weekend(naive_date) {
+        // If it is, return an error
+        return Err(ZastepstwaError::Weekend("Wybrana data to weekend!"));
+    }
+
+    // Check if the school has a break or a free day then
+    if calendar::is_holiday(holidays, naive_date) {
+        return Err(ZastepstwaError::Holiday(
+            "W tym dniu nie ma lekcji! Możesz odpoczywać!",
+        ));
+    }
+    Ok(naive_date)
+}
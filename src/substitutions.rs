@@ -0,0 +1,209 @@
+use actix_web::{get, web, HttpResponse};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use crate::error::ZastepstwaError;
+use crate::index::sha256_hex;
+use crate::parser::{self, ParseError, Substitution};
+use crate::state::AppState;
+use crate::{requested_date, Date};
+
+// The records parsed from one PDF, or why it couldn't be parsed
+type Table = Result<Arc<Vec<Substitution>>, ParseError>;
+
+// How many parsed versions are kept in memory. The feeds and calendars read a few dozen,
+// anything older is simply parsed again when someone asks for it.
+const CAPACITY: usize = 256;
+
+// When it's full, the least recently used table goes first
+#[derive(Default)]
+struct Tables {
+    tables: HashMap<String, (Table, u64)>,
+    // Counts every use, so the oldest use has the smallest number
+    clock: u64,
+}
+
+impl Tables {
+    fn get(&mut self, sha256: &str) -> Option<Table> {
+        self.clock += 1;
+        let clock = self.clock;
+        self.tables.get_mut(sha256).map(|(table, used)| {
+            *used = clock;
+            table.clone()
+        })
+    }
+
+    fn insert(&mut self, sha256: String, table: Table) {
+        self.clock += 1;
+        if self.tables.len() >= CAPACITY && !self.tables.contains_key(&sha256) {
+            let oldest = self
+                .tables
+                .iter()
+                .min_by_key(|(_, (_, used))| *used)
+                .map(|(sha256, _)| sha256.clone());
+            if let Some(oldest) = oldest {
+                self.tables.remove(&oldest);
+            }
+        }
+        self.tables.insert(sha256, (table, self.clock));
+    }
+}
+
+// Parsed substitution tables by the SHA-256 of the PDF they come from, so a version is only parsed once
+// while it's in use
+#[derive(Default)]
+pub struct Parsed {
+    tables: Mutex<Tables>,
+}
+
+impl Parsed {
+    pub async fn read(&self, path: &Path) -> Result<Arc<Vec<Substitution>>, ZastepstwaError> {
+        let bytes = tokio::fs::read(path).await.map_err(|e| {
+            log::error!("Error while reading {}: {}", path.display(), e);
+            ZastepstwaError::Storage
+        })?;
+        let sha256 = sha256_hex(&bytes);
+        if let Some(table) = self.tables.lock().unwrap().get(&sha256) {
+            return table.map_err(|_| ZastepstwaError::ParseFailed);
+        }
+
+        // Parsing is CPU-bound, keep it away from the threads serving requests
+        let table = tokio::task::spawn_blocking(move || parser::parse(&bytes))
+            .await
+            .unwrap_or_else(|e| Err(ParseError::Pdf(e.to_string())))
+            .map(Arc::new);
+        if let Err(e) = &table {
+            log::warn!("Error while parsing {}: {}", path.display(), e);
+        }
+        // Broken files are remembered too, so they aren't parsed again on every request
+        self.tables.lock().unwrap().insert(sha256, table.clone());
+        table.map_err(|_| ZastepstwaError::ParseFailed)
+    }
+}
+
+// A PDF that couldn't be parsed is still served, so its link goes out with the reason instead of the records
+#[derive(Serialize)]
+struct ParseFailure {
+    error: String,
+    kind: &'static str,
+}
+
+// Either the records or why there are none
+type TableOrFailure = (Option<Arc<Vec<Substitution>>>, Option<ParseFailure>);
+
+async fn read_table(state: &AppState, date: &str) -> Result<TableOrFailure, ZastepstwaError> {
+    match state.parsed.read(&state.config.cached_pdf(date)).await {
+        Ok(table) => Ok((Some(table), None)),
+        Err(e @ ZastepstwaError::ParseFailed) => Ok((
+            None,
+            Some(ParseFailure {
+                error: e.to_string(),
+                kind: e.kind(),
+            }),
+        )),
+        Err(e) => Err(e),
+    }
+}
+
+// The substitutions for a date as JSON, next to the link to the PDF they come from
+#[derive(Serialize)]
+struct SubstitutionsResponse<'a> {
+    code: u16,
+    link: String,
+    // dd.mm.yyyy
+    date: String,
+    // null if the PDF couldn't be parsed, error and kind say why
+    substitutions: Option<&'a [Substitution]>,
+    #[serde(flatten)]
+    failure: Option<ParseFailure>,
+}
+
+// /substitutions?day=10&month=10&year=2022
+#[get("/substitutions")]
+async fn get_substitutions(
+    state: web::Data<AppState>,
+    date: web::Query<Date>,
+) -> Result<HttpResponse, ZastepstwaError> {
+    let ready = state.ready_file(&date).await?;
+    let (table, failure) = read_table(&state, &ready.name).await?;
+    Ok(HttpResponse::Ok().json(SubstitutionsResponse {
+        code: 200,
+        link: ready.link,
+        date: ready.name,
+        substitutions: table.as_ref().map(|table| table.as_slice()),
+        failure,
+    }))
+}
+
+#[derive(Deserialize)]
+struct ClassQuery {
+    date: Option<String>,
+    when: Option<String>,
+    class: String,
+    group: Option<String>,
+}
+
+#[derive(Serialize)]
+struct ClassResponse<'a> {
+    code: u16,
+    link: String,
+    date: String,
+    class: &'a str,
+    group: Option<&'a str>,
+    substitutions: Option<Vec<&'a Substitution>>,
+    #[serde(flatten)]
+    failure: Option<ParseFailure>,
+}
+
+// Only the substitutions of one class (and optionally one group)
+// /api/substitutions?date=10.10.2022&class=3TI
+// /api/substitutions?when=tomorrow&class=3TI&group=1
+#[get("/api/substitutions")]
+async fn class_substitutions(
+    state: web::Data<AppState>,
+    query: web::Query<ClassQuery>,
+) -> Result<HttpResponse, ZastepstwaError> {
+    let date = requested_date(query.date.as_deref(), query.when.as_deref())?;
+    let ready = state.ready_file(&date).await?;
+    let (table, failure) = read_table(&state, &ready.name).await?;
+    let substitutions = table.as_ref().map(|table| {
+        table
+            .iter()
+            .filter(|record| record.is_for(&query.class, query.group.as_deref()))
+            .collect()
+    });
+    Ok(HttpResponse::Ok().json(ClassResponse {
+        code: 200,
+        link: ready.link,
+        date: ready.name,
+        class: &query.class,
+        group: query.group.as_deref(),
+        substitutions,
+        failure,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn least_recently_used_goes_first() {
+        let mut tables = Tables::default();
+        for n in 0..CAPACITY {
+            tables.insert(n.to_string(), Ok(Arc::new(Vec::new())));
+        }
+        // "0" is used again, so "1" is now the oldest
+        assert!(tables.get("0").is_some());
+        tables.insert(
+            "new".to_string(),
+            Err(ParseError::Pdf("broken".to_string())),
+        );
+        assert_eq!(tables.tables.len(), CAPACITY);
+        assert!(tables.get("1").is_none());
+        assert!(tables.get("0").is_some());
+        assert!(tables.get("new").unwrap().is_err());
+    }
+}
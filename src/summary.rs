@@ -0,0 +1,217 @@
+use actix_web::{get, web, HttpResponse};
+use chrono::Datelike;
+use serde::{Deserialize, Serialize};
+
+use crate::error::ZastepstwaError;
+use crate::requested_date;
+use crate::state::AppState;
+use crate::timetable::{Bell, EffectiveLesson, Status, Timetable};
+
+// First or last lesson of the day
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct Boundary {
+    pub lesson: u32,
+    pub bell: Option<Bell>,
+}
+
+// When a class actually starts and finishes, compared to the plan
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Summary {
+    pub first: Option<Boundary>,
+    pub last: Option<Boundary>,
+    pub planned_first: Option<Boundary>,
+    pub planned_last: Option<Boundary>,
+    pub late_start: bool,
+    pub early_finish: bool,
+    // Short Polish sentence for widgets and shortcuts
+    pub text: String,
+}
+
+// Work out the first and last lesson from the effective timetable of one class
+pub fn summarize(lessons: &[EffectiveLesson]) -> Summary {
+    let boundary = |lesson: &EffectiveLesson| Boundary {
+        lesson: lesson.lesson,
+        bell: lesson.bell,
+    };
+    let planned: Vec<&EffectiveLesson> = lessons
+        .iter()
+        .filter(|lesson| lesson.planned.is_some())
+        .collect();
+    let held: Vec<&EffectiveLesson> = lessons
+        .iter()
+        .filter(|lesson| lesson.status != Status::Cancelled)
+        .collect();
+
+    let planned_first = planned
+        .iter()
+        .min_by_key(|lesson| lesson.lesson)
+        .map(|lesson| boundary(lesson));
+    let planned_last = planned
+        .iter()
+        .max_by_key(|lesson| lesson.lesson)
+        .map(|lesson| boundary(lesson));
+    let first = held
+        .iter()
+        .min_by_key(|lesson| lesson.lesson)
+        .map(|lesson| boundary(lesson));
+    let last = held
+        .iter()
+        .max_by_key(|lesson| lesson.lesson)
+        .map(|lesson| boundary(lesson));
+
+    let late_start = matches!(
+        (first, planned_first),
+        (Some(first), Some(planned)) if first.lesson > planned.lesson
+    );
+    let early_finish = matches!(
+        (last, planned_last),
+        (Some(last), Some(planned)) if last.lesson < planned.lesson
+    );
+
+    let text = match (first, last) {
+        (Some(first), Some(last)) => format!(
+            "Zaczynacie {}od {} lekcji{}, kończycie {}po {} lekcji{}.",
+            if late_start { "" } else { "normalnie " },
+            first.lesson,
+            time(first.bell.map(|bell| bell.start)),
+            if early_finish { "" } else { "normalnie " },
+            last.lesson,
+            time(last.bell.map(|bell| bell.end)),
+        ),
+        _ if planned.is_empty() => "Nie macie lekcji w tym dniu.".to_string(),
+        _ => "Wszystkie lekcje są odwołane!".to_string(),
+    };
+
+    Summary {
+        first,
+        last,
+        planned_first,
+        planned_last,
+        late_start,
+        early_finish,
+        text,
+    }
+}
+
+// " (9:50)"
+fn time(time: Option<chrono::NaiveTime>) -> String {
+    time.map(|time| format!(" ({})", time.format("%-H:%M")))
+        .unwrap_or_default()
+}
+
+#[derive(Deserialize)]
+struct SummaryQuery {
+    date: Option<String>,
+    when: Option<String>,
+    class: String,
+    group: Option<String>,
+}
+
+#[derive(Serialize)]
+struct SummaryResponse<'a> {
+    code: u16,
+    // None when there are no substitutions for the date
+    link: Option<String>,
+    date: String,
+    class: &'a str,
+    group: Option<&'a str>,
+    summary: Summary,
+}
+
+// When a class starts and finishes after the cancellations, with a sentence to show instead of the PDF
+// /api/summary?when=tomorrow&class=3TI
+// /api/summary?date=10.10.2022&class=3TI&group=1
+#[get("/api/summary")]
+async fn class_summary(
+    state: web::Data<AppState>,
+    timetable: web::Data<Timetable>,
+    query: web::Query<SummaryQuery>,
+) -> Result<HttpResponse, ZastepstwaError> {
+    if timetable.is_empty() {
+        return Err(ZastepstwaError::NotFound(
+            "Plan lekcji nie został zaimportowany".to_string(),
+        ));
+    }
+    let date = requested_date(query.date.as_deref(), query.when.as_deref())?;
+    let day = state.for_plan(&date).await?;
+    let lessons: Vec<EffectiveLesson> = timetable
+        .effective(day.date.weekday(), &day.substitutions)
+        .into_iter()
+        .filter(|lesson| lesson.is_for(&query.class, query.group.as_deref()))
+        .collect();
+
+    let mut summary = summarize(&lessons);
+    if day.link.is_none() {
+        summary
+            .text
+            .push_str(" Zastępstwa na ten dzień nie zostały jeszcze opublikowane.");
+    }
+    Ok(HttpResponse::Ok().json(SummaryResponse {
+        code: 200,
+        link: day.link,
+        date: day.name,
+        class: &query.class,
+        group: query.group.as_deref(),
+        summary,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveTime;
+
+    fn lesson(number: u32, status: Status) -> EffectiveLesson {
+        let hour = 7 + number;
+        EffectiveLesson {
+            lesson: number,
+            class: "3TI".to_string(),
+            group: None,
+            subject: None,
+            teacher: None,
+            room: None,
+            status,
+            note: None,
+            bell: Some(Bell {
+                start: NaiveTime::from_hms_opt(hour, 0, 0).unwrap(),
+                end: NaiveTime::from_hms_opt(hour, 45, 0).unwrap(),
+            }),
+            planned: Some(crate::timetable::Lesson {
+                class: "3TI".to_string(),
+                group: None,
+                weekday: chrono::Weekday::Mon,
+                lesson: number,
+                subject: String::new(),
+                teacher: None,
+                room: None,
+            }),
+        }
+    }
+
+    #[test]
+    fn late_start_and_early_finish() {
+        let day = [
+            lesson(1, Status::Cancelled),
+            lesson(2, Status::Cancelled),
+            lesson(3, Status::Substituted),
+            lesson(4, Status::Normal),
+            lesson(5, Status::Cancelled),
+        ];
+        let summary = summarize(&day);
+        assert!(summary.late_start && summary.early_finish);
+        assert_eq!(
+            summary.text,
+            "Zaczynacie od 3 lekcji (10:00), kończycie po 4 lekcji (11:45)."
+        );
+
+        let summary = summarize(&day[2..4]);
+        assert!(!summary.late_start && !summary.early_finish);
+        assert_eq!(
+            summary.text,
+            "Zaczynacie normalnie od 3 lekcji (10:00), kończycie normalnie po 4 lekcji (11:45)."
+        );
+
+        assert_eq!(summarize(&day[..2]).text, "Wszystkie lekcje są odwołane!");
+        assert_eq!(summarize(&[]).text, "Nie macie lekcji w tym dniu.");
+    }
+}
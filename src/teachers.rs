@@ -0,0 +1,244 @@
+use actix_web::{get, web, HttpResponse};
+use chrono::Datelike;
+use serde::{Deserialize, Serialize};
+
+use crate::calendar;
+use crate::config::Holiday;
+use crate::error::ZastepstwaError;
+use crate::parser::Substitution;
+use crate::state::AppState;
+use crate::{requested_date, Date};
+
+#[derive(Deserialize)]
+struct TeacherQuery {
+    name: String,
+    date: Option<String>,
+    when: Option<String>,
+    // range=week
+    range: Option<String>,
+    // role=substitute|absent, both if not given
+    role: Option<String>,
+}
+
+// One day of the answer, in a week a day that couldn't be read only says why
+#[derive(Serialize)]
+#[serde(untagged)]
+enum TeacherDay {
+    Lessons {
+        date: String,
+        link: String,
+        // Left out when the role filter excludes them
+        #[serde(skip_serializing_if = "Option::is_none")]
+        duties: Option<Vec<Substitution>>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        absences: Option<Vec<Substitution>>,
+    },
+    Error {
+        date: String,
+        error: String,
+        kind: &'static str,
+    },
+}
+
+#[derive(Serialize)]
+struct TeacherResponse<'a> {
+    code: u16,
+    teacher: &'a str,
+    days: Vec<TeacherDay>,
+}
+
+// Lessons a teacher covers for someone else ("duties") and their own lessons covered by others ("absences")
+// /api/teacher?name=Kowalski&when=today
+// /api/teacher?name=J. Kowalski&range=week&role=substitute
+#[get("/api/teacher")]
+async fn teacher_substitutions(
+    state: web::Data<AppState>,
+    query: web::Query<TeacherQuery>,
+) -> Result<HttpResponse, ZastepstwaError> {
+    if query.name.trim().is_empty() {
+        return Err(ZastepstwaError::InvalidParameter(
+            "Parametr 'name' nie może być pusty".to_string(),
+        ));
+    }
+    let (duties, absences) = match query.role.as_deref() {
+        None => (true, true),
+        Some("substitute") => (true, false),
+        Some("absent") => (false, true),
+        Some(_) => {
+            return Err(ZastepstwaError::InvalidParameter(
+                "Nieprawidłowa wartość parametru 'role'".to_string(),
+            ))
+        }
+    };
+    let dates = match query.range.as_deref() {
+        None => vec![requested_date(
+            query.date.as_deref(),
+            query.when.as_deref(),
+        )?],
+        Some("week") if query.date.is_none() && query.when.is_none() => {
+            current_week(&state.config.school.holidays)
+        }
+        Some("week") => {
+            return Err(ZastepstwaError::InvalidParameter(
+                "Parametr 'range' nie może być podany razem z 'date' ani 'when'".to_string(),
+            ))
+        }
+        Some(_) => {
+            return Err(ZastepstwaError::InvalidParameter(
+                "Nieprawidłowa wartość parametru 'range'".to_string(),
+            ))
+        }
+    };
+
+    // With a single date its error is the response, in a week every day gets its own result
+    let single = query.range.is_none();
+    let mut days = Vec::new();
+    for date in dates {
+        let day = async {
+            let ready = state.ready_file(&date).await?;
+            let table = state
+                .parsed
+                .read(&state.config.cached_pdf(&ready.name))
+                .await?;
+            let lessons = |matches: fn(&Substitution, &str) -> bool| {
+                table
+                    .iter()
+                    .filter(|record| matches(record, &query.name))
+                    .cloned()
+                    .collect::<Vec<_>>()
+            };
+            Ok::<TeacherDay, ZastepstwaError>(TeacherDay::Lessons {
+                duties: duties.then(|| {
+                    lessons(|record, name| {
+                        record
+                            .substitute_teacher
+                            .as_deref()
+                            .is_some_and(|teacher| teacher_matches(teacher, name))
+                    })
+                }),
+                absences: absences
+                    .then(|| lessons(|record, name| teacher_matches(&record.absent_teacher, name))),
+                date: ready.name,
+                link: ready.link,
+            })
+        };
+        match day.await {
+            Ok(day) => days.push(day),
+            // Maintenance is the same for every day, so it's the answer for the whole week too
+            Err(e) if single || matches!(e, ZastepstwaError::Maintenance(_)) => return Err(e),
+            Err(e) => days.push(TeacherDay::Error {
+                date: format!("{:02}.{:02}.{}", date.day, date.month, date.year),
+                error: e.to_string(),
+                kind: e.kind(),
+            }),
+        }
+    }
+
+    Ok(HttpResponse::Ok().json(TeacherResponse {
+        code: 200,
+        teacher: &query.name,
+        days,
+    }))
+}
+
+// The school days of this week, on weekends of the next one. A week of a break has none.
+fn current_week(holidays: &[Holiday]) -> Vec<Date> {
+    let today = chrono::Local::now().date_naive();
+    let monday = today - chrono::Duration::days(today.weekday().num_days_from_monday() as i64);
+    let monday = if calendar::is_weekend(today) {
+        monday + chrono::Duration::weeks(1)
+    } else {
+        monday
+    };
+    (0..7)
+        .map(|offset| monday + chrono::Duration::days(offset))
+        .filter(|date| calendar::is_school_day(holidays, *date))
+        .map(Date::from)
+        .collect()
+}
+
+// Does a name from the PDF match what the user typed? Case and Polish letters don't matter,
+// every typed word has to match a word of the name and single letters match as initials,
+// so "Jan Kowalski" is found by "kowalski", "J. Kowalski", "Kowalski Jan" and "JK".
+pub fn teacher_matches(name: &str, query: &str) -> bool {
+    let name = words(name);
+    let query = words(query);
+    if query.is_empty() {
+        return false;
+    }
+    if query
+        .iter()
+        .all(|typed| name.iter().any(|word| word_matches(word, typed)))
+    {
+        return true;
+    }
+
+    // Only initials, in either order
+    match &query[..] {
+        [typed] if name.len() > 1 && typed.chars().count() == name.len() => {
+            let initials: String = name.iter().filter_map(|word| word.chars().next()).collect();
+            *typed == initials || *typed == initials.chars().rev().collect::<String>()
+        }
+        _ => false,
+    }
+}
+
+fn word_matches(word: &str, typed: &str) -> bool {
+    word == typed
+        || (typed.chars().count() == 1 && word.starts_with(typed))
+        || (word.chars().count() == 1 && typed.starts_with(word))
+}
+
+// "J. Wiśniewski" -> ["j", "wisniewski"]
+fn words(text: &str) -> Vec<String> {
+    text.split(|c: char| c.is_whitespace() || c == '.')
+        .filter(|word| !word.is_empty())
+        .map(|word| {
+            word.chars()
+                .flat_map(char::to_lowercase)
+                .map(plain_letter)
+                .collect()
+        })
+        .collect()
+}
+
+fn plain_letter(c: char) -> char {
+    match c {
+        'ą' => 'a',
+        'ć' => 'c',
+        'ę' => 'e',
+        'ł' => 'l',
+        'ń' => 'n',
+        'ó' => 'o',
+        'ś' => 's',
+        'ź' | 'ż' => 'z',
+        c => c,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::teacher_matches;
+
+    #[test]
+    fn name_matching() {
+        for typed in [
+            "Jan Kowalski",
+            "kowalski",
+            "J. Kowalski",
+            "J.Kowalski",
+            "Kowalski Jan",
+            "jan k",
+            "JK",
+            "kj",
+        ] {
+            assert!(teacher_matches("Jan Kowalski", typed), "{}", typed);
+        }
+        assert!(teacher_matches("Piotr Wiśniewski", "wisniewski"));
+        assert!(teacher_matches("Małgorzata Dąbrowska", "M. DĄBROWSKA"));
+
+        for typed in ["Nowak", "A. Kowalski", "Kowal", "JKX", ""] {
+            assert!(!teacher_matches("Jan Kowalski", typed), "{}", typed);
+        }
+    }
+}
@@ -0,0 +1,590 @@
+use actix_web::{get, web, HttpResponse};
+use chrono::{Datelike, NaiveTime, Weekday};
+use scraper::{ElementRef, Html, Selector};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use crate::error::ZastepstwaError;
+use crate::parser::{normalize, Kind, Substitution};
+use crate::requested_date;
+use crate::state::AppState;
+
+// One lesson of the normal plan
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Lesson {
+    pub class: String,
+    pub group: Option<String>,
+    pub weekday: Weekday,
+    pub lesson: u32,
+    pub subject: String,
+    pub teacher: Option<String>,
+    pub room: Option<String>,
+}
+
+// When a lesson starts and ends
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct Bell {
+    #[serde(serialize_with = "serialize_time")]
+    pub start: NaiveTime,
+    #[serde(serialize_with = "serialize_time")]
+    pub end: NaiveTime,
+}
+
+fn serialize_time<S: serde::Serializer>(
+    time: &NaiveTime,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&time.format("%H:%M").to_string())
+}
+
+// How a lesson of the plan looks after the substitutions
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Status {
+    Normal,
+    Substituted,
+    Cancelled,
+    Moved,
+}
+
+// A lesson as it actually takes place on a date
+#[derive(Debug, Clone, Serialize)]
+pub struct EffectiveLesson {
+    pub lesson: u32,
+    pub class: String,
+    pub group: Option<String>,
+    pub subject: Option<String>,
+    // Who teaches it and where, after the substitutions
+    pub teacher: Option<String>,
+    pub room: Option<String>,
+    pub status: Status,
+    pub note: Option<String>,
+    pub bell: Option<Bell>,
+    // The lesson from the normal plan, missing for lessons that only come from the substitutions
+    pub planned: Option<Lesson>,
+}
+
+#[derive(Debug)]
+pub enum TimetableError {
+    Io(PathBuf, std::io::Error),
+    // A file couldn't be understood, holds the reason
+    Invalid(PathBuf, String),
+}
+
+impl fmt::Display for TimetableError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TimetableError::Io(path, err) => {
+                write!(f, "couldn't read timetable {}: {}", path.display(), err)
+            }
+            TimetableError::Invalid(path, reason) => {
+                write!(f, "invalid timetable {}: {}", path.display(), reason)
+            }
+        }
+    }
+}
+
+impl std::error::Error for TimetableError {}
+
+// The normal plan of every class, imported at startup
+#[derive(Debug, Default)]
+pub struct Timetable {
+    pub lessons: Vec<Lesson>,
+    // Lesson number -> when it takes place
+    pub bells: BTreeMap<u32, Bell>,
+}
+
+impl Timetable {
+    // Import the plan from a CSV file, an HTML page of one class exported by Plan lekcji Optivum,
+    // or a folder with the whole Optivum export (o1.html, o2.html, ... for the classes, directly or in plany/)
+    pub fn load(path: &Path) -> Result<Timetable, TimetableError> {
+        let mut timetable = Timetable::default();
+        if path.is_dir() {
+            let mut dir = path.to_path_buf();
+            if dir.join("plany").is_dir() {
+                dir = dir.join("plany");
+            }
+            let entries =
+                std::fs::read_dir(&dir).map_err(|e| TimetableError::Io(dir.clone(), e))?;
+            let mut pages: Vec<PathBuf> = entries
+                .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+                .filter(|page| is_class_page(page))
+                .collect();
+            pages.sort();
+            for page in pages {
+                timetable.add_html(&page)?;
+            }
+        } else if path.extension().is_some_and(|extension| extension == "csv") {
+            timetable.add_csv(path)?;
+        } else {
+            timetable.add_html(path)?;
+        }
+        Ok(timetable)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.lessons.is_empty()
+    }
+
+    fn add_html(&mut self, path: &Path) -> Result<(), TimetableError> {
+        let bytes = std::fs::read(path).map_err(|e| TimetableError::Io(path.to_path_buf(), e))?;
+        // Older Optivum versions export in ISO-8859-2
+        let html = match String::from_utf8(bytes) {
+            Ok(html) => html,
+            Err(e) => encoding_rs::ISO_8859_2.decode(e.as_bytes()).0.into_owned(),
+        };
+        let invalid =
+            |reason: &str| TimetableError::Invalid(path.to_path_buf(), reason.to_string());
+        let page = Html::parse_document(&html);
+
+        let class = page
+            .select(&selector(".tytulnapis"))
+            .next()
+            .and_then(|title| text(title).split_whitespace().next().map(str::to_string))
+            .ok_or_else(|| invalid("no class name (.tytulnapis)"))?;
+        let table = page
+            .select(&selector("table.tabela"))
+            .next()
+            .ok_or_else(|| invalid("no timetable (table.tabela)"))?;
+
+        // The header says which column is which day
+        let days: Vec<Option<Weekday>> = table
+            .select(&selector("th"))
+            .map(|header| parse_weekday(&text(header)))
+            .collect();
+        for row in table.select(&selector("tr")) {
+            let Some(number) = row
+                .select(&selector("td.nr"))
+                .next()
+                .and_then(|cell| text(cell).parse().ok())
+            else {
+                continue;
+            };
+            if let Some(bell) = row
+                .select(&selector("td.g"))
+                .next()
+                .and_then(|cell| parse_bell(&text(cell)))
+            {
+                self.bells.insert(number, bell);
+            }
+            // Lesson cells come after the number and hours, in the same order as the days in the header
+            let cell_selector = selector("td");
+            for (cell, day) in row.select(&cell_selector).zip(days.iter()) {
+                let Some(weekday) = day else { continue };
+                if !cell.value().classes().any(|class| class == "l") {
+                    continue;
+                }
+                // Lessons of different groups in one cell are separated with <br>
+                for part in cell.inner_html().split("<br>") {
+                    let part = Html::parse_fragment(part);
+                    let first = |name: &str| {
+                        part.select(&selector(name))
+                            .next()
+                            .map(text)
+                            .filter(|text| !text.is_empty())
+                    };
+                    let Some(subject) = first(".p") else { continue };
+                    let (subject, group) = split_group(&subject);
+                    self.lessons.push(Lesson {
+                        class: class.clone(),
+                        group,
+                        weekday: *weekday,
+                        lesson: number,
+                        subject,
+                        teacher: first(".n"),
+                        room: first(".s"),
+                    });
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn add_csv(&mut self, path: &Path) -> Result<(), TimetableError> {
+        let mut reader = csv::Reader::from_path(path).map_err(|e| csv_error(path, e))?;
+        for row in reader.deserialize() {
+            let row: CsvRow = row.map_err(|e| csv_error(path, e))?;
+            let weekday = parse_weekday(&row.weekday).ok_or_else(|| {
+                TimetableError::Invalid(
+                    path.to_path_buf(),
+                    format!("unknown weekday {:?}", row.weekday),
+                )
+            })?;
+            if let (Some(start), Some(end)) = (&row.start, &row.end) {
+                let bell = parse_bell(&format!("{}-{}", start, end)).ok_or_else(|| {
+                    TimetableError::Invalid(
+                        path.to_path_buf(),
+                        format!("invalid hours {}-{}", start, end),
+                    )
+                })?;
+                self.bells.insert(row.lesson, bell);
+            }
+            self.lessons.push(Lesson {
+                class: row.class,
+                group: row.group,
+                weekday,
+                lesson: row.lesson,
+                subject: row.subject,
+                teacher: row.teacher,
+                room: row.room,
+            });
+        }
+        Ok(())
+    }
+
+    // Every lesson of every class on a day of the week, with the substitutions for that date applied.
+    // Substitutions for lessons that aren't in the plan are added as extra lessons.
+    pub fn effective(
+        &self,
+        weekday: Weekday,
+        substitutions: &[Substitution],
+    ) -> Vec<EffectiveLesson> {
+        let mut lessons: Vec<EffectiveLesson> = self
+            .lessons
+            .iter()
+            .filter(|lesson| lesson.weekday == weekday)
+            .map(|lesson| EffectiveLesson {
+                lesson: lesson.lesson,
+                class: lesson.class.clone(),
+                group: lesson.group.clone(),
+                subject: Some(lesson.subject.clone()),
+                teacher: lesson.teacher.clone(),
+                room: lesson.room.clone(),
+                status: Status::Normal,
+                note: None,
+                bell: self.bells.get(&lesson.lesson).copied(),
+                planned: Some(lesson.clone()),
+            })
+            .collect();
+
+        for record in substitutions {
+            let (status, teacher, room) = match record.kind {
+                Kind::Cancelled => (Status::Cancelled, None, None),
+                Kind::Moved => (
+                    Status::Moved,
+                    record.substitute_teacher.clone(),
+                    record.moved_to().or_else(|| record.room.clone()),
+                ),
+                Kind::Substituted | Kind::Joined => (
+                    Status::Substituted,
+                    record.substitute_teacher.clone(),
+                    record.room.clone(),
+                ),
+            };
+            let mut found = false;
+            for lesson in lessons.iter_mut() {
+                let affected = lesson.planned.is_some()
+                    && lesson.lesson == record.lesson
+                    && record.is_for(&lesson.class, None)
+                    && (record.group.is_none()
+                        || lesson.group.is_none()
+                        || lesson.group == record.group);
+                if !affected {
+                    continue;
+                }
+                found = true;
+                lesson.status = status;
+                lesson.note = record.note.clone();
+                if status == Status::Cancelled {
+                    // Nobody teaches it and it doesn't use its room, the plan is still in `planned`
+                    lesson.teacher = None;
+                    lesson.room = None;
+                } else {
+                    lesson.teacher = teacher.clone().or(lesson.teacher.take());
+                    lesson.room = room.clone().or(lesson.room.take());
+                }
+            }
+            if !found {
+                lessons.push(EffectiveLesson {
+                    lesson: record.lesson,
+                    class: record.class.clone(),
+                    group: record.group.clone(),
+                    subject: record.subject.clone(),
+                    teacher,
+                    room,
+                    status,
+                    note: record.note.clone(),
+                    bell: self.bells.get(&record.lesson).copied(),
+                    planned: None,
+                });
+            }
+        }
+
+        lessons.sort_by(|a, b| {
+            (a.class.as_str(), a.lesson, a.group.as_deref()).cmp(&(
+                b.class.as_str(),
+                b.lesson,
+                b.group.as_deref(),
+            ))
+        });
+        lessons
+    }
+}
+
+impl EffectiveLesson {
+    // Same rules as for substitutions: without a group every lesson of the class counts,
+    // with one only lessons for the whole class and for that group do
+    pub fn is_for(&self, class: &str, group: Option<&str>) -> bool {
+        normalize(&self.class) == normalize(class)
+            && match (&self.group, group) {
+                (Some(own), Some(group)) => normalize(own) == normalize(group),
+                _ => true,
+            }
+    }
+}
+
+#[derive(Deserialize)]
+struct TimetableQuery {
+    date: Option<String>,
+    when: Option<String>,
+    class: String,
+    group: Option<String>,
+}
+
+#[derive(Serialize)]
+struct TimetableResponse<'a> {
+    code: u16,
+    // None when there are no substitutions for the date
+    link: Option<String>,
+    date: String,
+    class: &'a str,
+    group: Option<&'a str>,
+    lessons: Vec<EffectiveLesson>,
+}
+
+// The plan of a class for a date with the substitutions applied
+// /api/timetable?date=10.10.2022&class=3TI
+// /api/timetable?when=tomorrow&class=3TI&group=1
+#[get("/api/timetable")]
+async fn class_timetable(
+    state: web::Data<AppState>,
+    timetable: web::Data<Timetable>,
+    query: web::Query<TimetableQuery>,
+) -> Result<HttpResponse, ZastepstwaError> {
+    if timetable.is_empty() {
+        return Err(ZastepstwaError::NotFound(
+            "Plan lekcji nie został zaimportowany".to_string(),
+        ));
+    }
+    let date = requested_date(query.date.as_deref(), query.when.as_deref())?;
+    let day = state.for_plan(&date).await?;
+    let lessons: Vec<EffectiveLesson> = timetable
+        .effective(day.date.weekday(), &day.substitutions)
+        .into_iter()
+        .filter(|lesson| lesson.is_for(&query.class, query.group.as_deref()))
+        .collect();
+    Ok(HttpResponse::Ok().json(TimetableResponse {
+        code: 200,
+        link: day.link,
+        date: day.name,
+        class: &query.class,
+        group: query.group.as_deref(),
+        lessons,
+    }))
+}
+
+#[derive(Deserialize)]
+struct CsvRow {
+    class: String,
+    // 1-5 or the name of the day
+    weekday: String,
+    lesson: u32,
+    start: Option<String>,
+    end: Option<String>,
+    subject: String,
+    teacher: Option<String>,
+    room: Option<String>,
+    group: Option<String>,
+}
+
+fn csv_error(path: &Path, error: csv::Error) -> TimetableError {
+    TimetableError::Invalid(path.to_path_buf(), error.to_string())
+}
+
+// In the Optivum export the classes are o1.html, o2.html... (teachers are n*.html and rooms s*.html)
+fn is_class_page(path: &Path) -> bool {
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    name.ends_with(".html")
+        && name
+            .strip_prefix('o')
+            .is_some_and(|rest| rest.starts_with(|c: char| c.is_ascii_digit()))
+}
+
+fn selector(selector: &str) -> Selector {
+    Selector::parse(selector).unwrap()
+}
+
+fn text(element: ElementRef) -> String {
+    element
+        .text()
+        .collect::<String>()
+        .replace('\u{a0}', " ")
+        .trim()
+        .to_string()
+}
+
+// "Poniedziałek", "pon", "1" or "Mon"
+fn parse_weekday(text: &str) -> Option<Weekday> {
+    let text = text.trim().to_lowercase();
+    let weekday = match text.as_str() {
+        "1" => Weekday::Mon,
+        "2" => Weekday::Tue,
+        "3" => Weekday::Wed,
+        "4" => Weekday::Thu,
+        "5" => Weekday::Fri,
+        _ if text.starts_with("pon") => Weekday::Mon,
+        _ if text.starts_with("wt") => Weekday::Tue,
+        _ if text.starts_with("śr") || text.starts_with("sr") => Weekday::Wed,
+        _ if text.starts_with("czw") => Weekday::Thu,
+        _ if text.starts_with("pi") || text.starts_with("pt") => Weekday::Fri,
+        _ => return text.parse().ok(),
+    };
+    Some(weekday)
+}
+
+// " 8:00- 8:45"
+fn parse_bell(text: &str) -> Option<Bell> {
+    let (start, end) = text.split_once('-')?;
+    Some(Bell {
+        start: NaiveTime::parse_from_str(start.trim(), "%H:%M").ok()?,
+        end: NaiveTime::parse_from_str(end.trim(), "%H:%M").ok()?,
+    })
+}
+
+// Optivum writes groups as "informatyka-1/2" (group 1 of 2)
+fn split_group(subject: &str) -> (String, Option<String>) {
+    if let Some((name, group)) = subject.rsplit_once('-') {
+        if let Some((number, _)) = group.split_once('/') {
+            if !number.is_empty() && number.chars().all(|c| c.is_ascii_digit()) {
+                return (name.trim().to_string(), Some(number.to_string()));
+            }
+        }
+    }
+    (subject.trim().to_string(), None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn plan() -> Timetable {
+        let mut timetable = Timetable::load(Path::new("tests/fixtures/plan")).unwrap();
+        timetable
+            .add_csv(Path::new("tests/fixtures/plan.csv"))
+            .unwrap();
+        timetable
+    }
+
+    #[test]
+    fn optivum_import() {
+        let timetable = Timetable::load(Path::new("tests/fixtures/plan")).unwrap();
+        let monday: Vec<_> = timetable
+            .lessons
+            .iter()
+            .filter(|lesson| lesson.weekday == Weekday::Mon)
+            .map(|lesson| {
+                (
+                    lesson.lesson,
+                    lesson.subject.as_str(),
+                    lesson.group.as_deref(),
+                    lesson.room.as_deref(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            monday,
+            [
+                (1, "matematyka", None, Some("12")),
+                (2, "informatyka", Some("1"), Some("105")),
+                (2, "informatyka", Some("2"), Some("104")),
+                (3, "fizyka", None, Some("5")),
+                (4, "wf", None, Some("sg")),
+            ]
+        );
+        assert!(timetable.lessons.iter().all(|lesson| lesson.class == "3TI"));
+        assert_eq!(timetable.lessons.len(), 6);
+        assert_eq!(timetable.bells[&3], parse_bell("9:50-10:35").unwrap());
+    }
+
+    #[test]
+    fn csv_import() {
+        let mut timetable = Timetable::default();
+        timetable
+            .add_csv(Path::new("tests/fixtures/plan.csv"))
+            .unwrap();
+        assert_eq!(timetable.lessons.len(), 5);
+        assert_eq!(timetable.lessons[3].weekday, Weekday::Mon);
+        assert_eq!(timetable.lessons[4].weekday, Weekday::Tue);
+        assert_eq!(timetable.lessons[2].group, None);
+        assert_eq!(timetable.bells[&7], parse_bell("13:20-14:05").unwrap());
+    }
+
+    #[test]
+    fn substitutions_applied() {
+        let substitutions =
+            crate::parser::parse(include_bytes!("../tests/fixtures/basic.pdf")).unwrap();
+        let day = plan().effective(Weekday::Mon, &substitutions);
+        let status = |class: &str, lesson: u32, group: Option<&str>| {
+            day.iter()
+                .find(|effective| {
+                    effective.class == class
+                        && effective.lesson == lesson
+                        && effective.group.as_deref() == group
+                })
+                .map(|effective| {
+                    (
+                        effective.status,
+                        effective.teacher.clone(),
+                        effective.room.clone(),
+                    )
+                })
+        };
+
+        assert_eq!(
+            status("3TI", 1, None),
+            Some((
+                Status::Substituted,
+                Some("Anna Nowak".to_string()),
+                Some("12".to_string())
+            ))
+        );
+        assert_eq!(
+            status("3TI", 2, Some("1")),
+            Some((
+                Status::Substituted,
+                Some("Piotr Wiśniewski".to_string()),
+                Some("105".to_string())
+            ))
+        );
+        assert_eq!(
+            status("3TI", 2, Some("2")),
+            Some((
+                Status::Normal,
+                Some("AN".to_string()),
+                Some("104".to_string())
+            ))
+        );
+        assert_eq!(
+            status("4TE", 5, Some("2")),
+            Some((
+                Status::Moved,
+                Some("Tomasz Lewandowski".to_string()),
+                Some("14".to_string())
+            ))
+        );
+        assert_eq!(status("4TE", 5, Some("1")).unwrap().0, Status::Normal);
+        assert_eq!(status("4TE", 6, None).unwrap().0, Status::Cancelled);
+        assert_eq!(status("4TE", 7, None).unwrap().0, Status::Normal);
+        // Not in the plan, only in the substitutions
+        assert_eq!(status("2A", 3, None).unwrap().0, Status::Cancelled);
+        assert!(day.iter().all(|effective| effective
+            .planned
+            .as_ref()
+            .is_none_or(|planned| planned.weekday == Weekday::Mon)));
+    }
+}
@@ -0,0 +1,134 @@
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::config::ValidationConfig;
+
+// Downloads thrown away since the server started, shown in /stats
+static REJECTED: AtomicU64 = AtomicU64::new(0);
+
+// Why a download doesn't look like a substitution PDF
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Rejection {
+    // Captive portals and error pages usually say what they are in the header
+    ContentType(String),
+    TooSmall(usize),
+    // The file doesn't start with %PDF
+    NotPdf,
+    // The xref table or trailer couldn't be read, usually a truncated download
+    Malformed(String),
+    Pages(usize),
+}
+
+impl fmt::Display for Rejection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Rejection::ContentType(content_type) => {
+                write!(f, "unexpected content type {:?}", content_type)
+            }
+            Rejection::TooSmall(size) => write!(f, "only {} B", size),
+            Rejection::NotPdf => write!(f, "no %PDF header"),
+            Rejection::Malformed(reason) => write!(f, "unreadable PDF: {}", reason),
+            Rejection::Pages(pages) => write!(f, "unexpected page count {}", pages),
+        }
+    }
+}
+
+// Only the media type counts, not parameters like "; charset=binary"
+fn media_type(content_type: &str) -> String {
+    content_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase()
+}
+
+// Cheap checks go first, the PDF is only parsed if everything else looks right
+pub fn check(
+    rules: &ValidationConfig,
+    bytes: &[u8],
+    content_type: Option<&str>,
+) -> Result<(), Rejection> {
+    if let Some(content_type) = content_type {
+        let media_type = media_type(content_type);
+        if !rules
+            .content_types
+            .iter()
+            .any(|allowed| media_type == allowed.to_ascii_lowercase())
+        {
+            return Err(Rejection::ContentType(content_type.to_string()));
+        }
+    }
+    if (bytes.len() as u64) < rules.min_size {
+        return Err(Rejection::TooSmall(bytes.len()));
+    }
+    if !bytes.starts_with(b"%PDF") {
+        return Err(Rejection::NotPdf);
+    }
+    // Loading reads the xref table and the trailer, the same as the parser does later.
+    // lopdf panics on some broken files instead of returning an error.
+    let pages = std::panic::catch_unwind(|| {
+        pdf_extract::Document::load_mem(bytes)
+            .map(|doc| doc.get_pages().len())
+            .map_err(|e| Rejection::Malformed(e.to_string()))
+    })
+    .unwrap_or_else(|_| Err(Rejection::Malformed("the PDF is malformed".to_string())))?;
+    if pages < rules.min_pages as usize || rules.max_pages.is_some_and(|max| pages > max as usize) {
+        return Err(Rejection::Pages(pages));
+    }
+    Ok(())
+}
+
+pub fn record_rejection() {
+    REJECTED.fetch_add(1, Ordering::Relaxed);
+}
+
+pub fn rejected() -> u64 {
+    REJECTED.load(Ordering::Relaxed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn downloads() {
+        let rules = ValidationConfig::default();
+        let pdf: &[u8] = include_bytes!("../tests/fixtures/basic.pdf");
+        assert_eq!(check(&rules, pdf, Some("application/pdf")), Ok(()));
+        assert_eq!(check(&rules, pdf, None), Ok(()));
+        assert_eq!(
+            check(
+                &rules,
+                pdf,
+                Some("Application/Octet-Stream; charset=binary")
+            ),
+            Ok(())
+        );
+
+        let portal = "<html><body>Zaloguj się do sieci szkolnej</body></html>"
+            .repeat(40)
+            .into_bytes();
+        assert!(matches!(
+            check(&rules, &portal, Some("text/html; charset=utf-8")),
+            Err(Rejection::ContentType(_))
+        ));
+        assert_eq!(check(&rules, &portal, None), Err(Rejection::NotPdf));
+        assert_eq!(
+            check(&rules, &pdf[..100], None),
+            Err(Rejection::TooSmall(100))
+        );
+        assert!(matches!(
+            check(&rules, &pdf[..pdf.len() - 200], None),
+            Err(Rejection::Malformed(_))
+        ));
+
+        let rules = ValidationConfig {
+            max_pages: Some(1),
+            ..ValidationConfig::default()
+        };
+        let multipage: &[u8] = include_bytes!("../tests/fixtures/multipage.pdf");
+        assert_eq!(check(&rules, pdf, None), Ok(()));
+        assert_eq!(check(&rules, multipage, None), Err(Rejection::Pages(2)));
+    }
+}
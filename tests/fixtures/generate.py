@@ -0,0 +1,129 @@
+#!/usr/bin/env python3
+# Generates the sample substitution PDFs used by the parser's golden tests.
+# They mimic the layout of the "Zastępstwa" printout from Plan lekcji Optivum:
+# a title, then for every absent teacher their name and a table (lekcja | opis | zastępca | uwagi).
+#
+# Usage: python3 tests/fixtures/generate.py   (writes the PDFs next to this script)
+
+import os
+
+# Polish letters that aren't in WinAnsiEncoding get their own codes through /Differences
+POLISH = "ąćęłńśźżĄĆĘŁŃŚŹŻ"
+GLYPHS = "aogonek cacute eogonek lslash nacute sacute zacute zdotaccent " \
+         "Aogonek Cacute Eogonek Lslash Nacute Sacute Zacute Zdotaccent"
+FIRST_CODE = 128
+
+# Column positions, same as in the Optivum printout
+LEKCJA, OPIS, ZASTEPCA, UWAGI = 40, 90, 330, 460
+
+
+def encode(text):
+    out = bytearray()
+    for ch in text:
+        if ch in POLISH:
+            out.append(FIRST_CODE + POLISH.index(ch))
+        else:
+            code = ch.encode("cp1252")
+            if code in (b"(", b")", b"\\"):
+                out += b"\\"
+            out += code
+    return bytes(out)
+
+
+def page_content(lines):
+    out = bytearray()
+    for x, y, size, text in lines:
+        out += b"BT /F1 %d Tf %d %d Td (" % (size, x, y) + encode(text) + b") Tj ET\n"
+    return bytes(out)
+
+
+def write_pdf(path, pages):
+    objects = []
+    objects.append(b"<< /Type /Catalog /Pages 2 0 R >>")
+    kids = " ".join("%d 0 R" % (4 + 2 * i) for i in range(len(pages)))
+    objects.append(b"<< /Type /Pages /Kids [%s] /Count %d >>" % (kids.encode(), len(pages)))
+    objects.append(
+        b"<< /Type /Font /Subtype /Type1 /BaseFont /Helvetica /Encoding << /Type /Encoding "
+        b"/BaseEncoding /WinAnsiEncoding /Differences [%d %s] >> >>"
+        % (FIRST_CODE, " ".join("/" + g for g in GLYPHS.split()).encode())
+    )
+    for i, lines in enumerate(pages):
+        content = page_content(lines)
+        objects.append(
+            b"<< /Type /Page /Parent 2 0 R /MediaBox [0 0 595 842] "
+            b"/Resources << /Font << /F1 3 0 R >> >> /Contents %d 0 R >>" % (5 + 2 * i)
+        )
+        objects.append(b"<< /Length %d >>\nstream\n" % len(content) + content + b"endstream")
+
+    out = bytearray(b"%PDF-1.4\n")
+    offsets = []
+    for n, body in enumerate(objects, start=1):
+        offsets.append(len(out))
+        out += b"%d 0 obj\n" % n + body + b"\nendobj\n"
+    xref = len(out)
+    out += b"xref\n0 %d\n0000000000 65535 f \n" % (len(objects) + 1)
+    for offset in offsets:
+        out += b"%010d 00000 n \n" % offset
+    out += b"trailer\n<< /Size %d /Root 1 0 R >>\nstartxref\n%d\n%%%%EOF\n" % (len(objects) + 1, xref)
+    with open(path, "wb") as f:
+        f.write(out)
+
+
+class Page:
+    def __init__(self):
+        self.lines = []
+        self.y = 800
+
+    def text(self, x, text, size=9):
+        self.lines.append((x, self.y, size, text))
+
+    def row(self, lekcja="", opis="", zastepca="", uwagi=""):
+        for x, text in ((LEKCJA, lekcja), (OPIS, opis), (ZASTEPCA, zastepca), (UWAGI, uwagi)):
+            if text:
+                self.text(x, text)
+        self.y -= 14
+
+    def teacher(self, name):
+        self.y -= 8
+        self.text(LEKCJA, name, size=11)
+        self.y -= 16
+        self.row("lekcja", "opis", "zastępca", "uwagi")
+
+
+def basic():
+    page = Page()
+    page.text(LEKCJA, "Zastępstwa w dniu 10.10.2022 (poniedziałek)", size=14)
+    page.y -= 24
+    page.teacher("Jan Kowalski")
+    page.row("1", "3TI - matematyka, s. 12", "Anna Nowak")
+    page.row("2", "3TI gr.1 - informatyka, s. 105", "Piotr Wiśniewski")
+    page.row("3", "2A - fizyka, s. 5", "", "lekcja odwołana")
+    page.row("4", "1B, 1C - wf, s. sg", "Ewa Zielińska", "łączona")
+    page.teacher("Małgorzata Dąbrowska (nieobecna)")
+    page.row("5", "4TE|2 - j. angielski, s. 21", "Tomasz Lewandowski", "przeniesiona do s. 14")
+    page.row("6", "4TE - historia", "Uczniowie zwolnieni do domu")
+    write_pdf(os.path.join(HERE, "basic.pdf"), [page.lines])
+
+
+def multipage():
+    first = Page()
+    first.text(LEKCJA, "Zastępstwa w dniu 11.10.2022 (wtorek)", size=14)
+    first.y -= 24
+    first.teacher("Krzysztof Wójcik")
+    first.row("0", "1A - religia, s. 3", "Barbara Kamińska")
+    # Long descriptions wrap to the next line without a lesson number
+    first.row("1", "2TI gr. 2 - programowanie aplikacji", "Adam Kaczmarek")
+    first.row("", "internetowych, s. 104")
+    first.row("8", "3TE - elektronika, s. 7", "", "lekcja odwołana")
+    second = Page()
+    second.teacher("Zofia Mazur")
+    second.row("2", "1D - biologia (s. 15)", "Krzysztof Wójcik")
+    second.row("3", "1D - chemia", "", "odwołana")
+    write_pdf(os.path.join(HERE, "multipage.pdf"), [first.lines, second.lines])
+
+
+HERE = os.path.dirname(os.path.abspath(__file__))
+
+if __name__ == "__main__":
+    basic()
+    multipage()